@@ -0,0 +1,403 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use yaml_front_matter::YamlFrontMatter;
+
+use crate::{
+    client::Client, history::HistoryEntry, parse_metadata, publish, validate, ApiConfig, PublishArgs, PublishMetadata,
+    PublishStatus,
+};
+
+const MANIFEST_FILE_NAME: &str = ".markmedium-manifest";
+/// Files whose front matter every post in their directory inherits, in order of preference
+const DEFAULTS_FILE_NAMES: [&str; 2] = ["_defaults.yaml", "_defaults.md"];
+
+/// Front matter a directory's posts inherit from its `_defaults.yaml` or
+/// `_defaults.md`. Each file's own front matter wins, except that tags are
+/// combined; flags still win over both.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    status: Option<PublishStatus>,
+    /// Added after a file's own tags, skipping ones it has, up to Medium's limit
+    tags: Option<Vec<String>>,
+    /// Address a file's slug is appended to for its canonical URL, when it sets none
+    canonical_base: Option<String>,
+    notify_followers: Option<bool>,
+    publish: Option<bool>,
+}
+
+impl Defaults {
+    /// Whether these defaults give the front matter field `key` a value
+    pub fn sets(&self, key: &str) -> bool {
+        match key {
+            "status" => self.status.is_some(),
+            "tags" => self.tags.is_some(),
+            "canonical_url" => self.canonical_base.is_some(),
+            "notify_followers" => self.notify_followers.is_some(),
+            "publish" => self.publish.is_some(),
+            _ => false,
+        }
+    }
+
+    pub fn apply(&self, metadata: &mut PublishMetadata) -> Result<()> {
+        if metadata.status.is_none() {
+            metadata.status = self.status.clone();
+        }
+        if metadata.notify_followers.is_none() {
+            metadata.notify_followers = self.notify_followers;
+        }
+        if metadata.publish.is_none() {
+            metadata.publish = self.publish;
+        }
+        for tag in self.tags.iter().flatten() {
+            let tags = metadata.tags.get_or_insert_with(Vec::new);
+            if tags.len() < validate::MAX_TAGS && !validate::has_tag(tags, tag) {
+                tags.push(tag.to_owned());
+            }
+        }
+        if let (None, Some(base)) = (&metadata.canonical_url, &self.canonical_base) {
+            let mut base = url::Url::parse(base).map_err(|err| anyhow!("Invalid canonical_base {}: {}", base, err))?;
+            if !base.path().ends_with('/') {
+                base.set_path(&format!("{}/", base.path()));
+            }
+            metadata.canonical_url = Some(base.join(&metadata.slug())?.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The defaults file in `dir`, if it has one
+pub fn load_defaults(dir: &Path) -> Result<Option<Defaults>> {
+    for name in DEFAULTS_FILE_NAMES {
+        let path = dir.join(name);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let text = if name.ends_with(".yaml") {
+            format!("---\n{}\n---\n", text)
+        } else {
+            text
+        };
+        let document = YamlFrontMatter::parse::<Defaults>(&text)
+            .map_err(|err| anyhow!("Invalid defaults in {}: {}", path.display(), err))?;
+        return Ok(Some(document.metadata));
+    }
+    Ok(None)
+}
+
+/// Outcome of every file in the last batch run of a directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<PathBuf, Outcome>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+pub enum Outcome {
+    Success { url: String },
+    Failed { error: String },
+    #[serde(rename = "timed_out")]
+    TimedOut { seconds: u64 },
+}
+
+impl Manifest {
+    fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_FILE_NAME)
+    }
+
+    pub fn load(dir: &Path) -> Result<Manifest> {
+        match std::fs::read_to_string(Manifest::path(dir)) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::write(Manifest::path(dir), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn succeeded(&self, file: &Path) -> bool {
+        matches!(self.files.get(file), Some(Outcome::Success { .. }))
+    }
+}
+
+/// What happened to each file of a directory, printed as JSON with --json
+#[derive(Debug, Default, Serialize)]
+pub struct BatchSummary {
+    pub succeeded: Vec<PublishedFile>,
+    /// Files that failed or timed out
+    pub failed: Vec<FailedFile>,
+    pub skipped: Vec<PathBuf>,
+    /// Files never attempted because Ctrl-C stopped the run
+    pub remaining: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublishedFile {
+    pub file: PathBuf,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedFile {
+    pub file: PathBuf,
+    pub error: String,
+    pub timed_out: bool,
+}
+
+impl BatchSummary {
+    pub fn timed_out(&self) -> usize {
+        self.failed.iter().filter(|failure| failure.timed_out).count()
+    }
+}
+
+/// Turn the first Ctrl-C into a request to stop once the current file is
+/// done; a second one exits straight away
+fn stop_on_ctrl_c() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = stop.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        errln!("Stopping after the current file; press Ctrl-C again to quit now");
+        flag.store(true, Ordering::SeqCst);
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    stop
+}
+
+fn is_markdown(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("md") | Some("markdown")
+    )
+}
+
+/// Every markdown file below `dir`, relative to it and sorted by path
+pub fn markdown_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            let defaults = path
+                .file_name()
+                .is_some_and(|name| DEFAULTS_FILE_NAMES.iter().any(|defaults| name == *defaults));
+            if hidden || defaults {
+                continue;
+            }
+
+            if path.is_dir() {
+                walk(root, &path, files)?;
+            } else if is_markdown(&path) {
+                files.push(path.strip_prefix(root)?.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(dir, dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Order the files of a directory are published in
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Sort {
+    /// By path
+    Name,
+    /// Least recently modified first
+    Mtime,
+    /// By the front matter's `date`, or `scheduled_for`, oldest first
+    FrontmatterDate,
+    /// By `series.index`, so part 1 goes out before part 2
+    SeriesIndex,
+}
+
+/// When a front matter date such as `2024-05-01` or `2024-05-01T09:00:00Z` is
+fn parse_date(date: &str) -> Option<SystemTime> {
+    if date.len() == 10 {
+        humantime::parse_rfc3339_weak(&format!("{} 00:00:00", date)).ok()
+    } else {
+        humantime::parse_rfc3339_weak(date).ok()
+    }
+}
+
+/// Where `file` goes in `sort` order, or `None` when it lacks the field
+fn sort_key(dir: &Path, file: &Path, sort: Sort) -> Result<Option<u128>> {
+    let since_epoch = |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |age| age.as_nanos());
+    if let Sort::Mtime = sort {
+        return Ok(Some(since_epoch(std::fs::metadata(dir.join(file))?.modified()?)));
+    }
+    let text = std::fs::read_to_string(dir.join(file))?;
+    let Ok(document) = YamlFrontMatter::parse::<serde_json::Map<String, serde_json::Value>>(&text) else {
+        return Ok(None);
+    };
+    let front_matter = document.metadata;
+    Ok(match sort {
+        Sort::FrontmatterDate => {
+            let date = front_matter.get("date").or(front_matter.get("scheduled_for"));
+            match date.and_then(|date| date.as_str()) {
+                Some(date) => match parse_date(date) {
+                    Some(time) => Some(since_epoch(time)),
+                    None => {
+                        errln!("Warning: {}: can't sort by date {:?}, so it goes last", file.display(), date);
+                        None
+                    }
+                },
+                None => None,
+            }
+        }
+        Sort::SeriesIndex => front_matter
+            .get("series")
+            .and_then(|series| series.get("index"))
+            .and_then(|index| index.as_u64())
+            .map(u128::from),
+        Sort::Name | Sort::Mtime => None,
+    })
+}
+
+/// Reorder `files`, relative to `dir` and sorted by path, by `sort`. Files
+/// without the field sorted by keep their path order after the rest.
+pub fn sort_files(dir: &Path, files: Vec<PathBuf>, sort: Sort) -> Result<Vec<PathBuf>> {
+    if let Sort::Name = sort {
+        return Ok(files);
+    }
+    let mut keyed = Vec::with_capacity(files.len());
+    for file in files {
+        keyed.push((sort_key(dir, &file, sort)?, file));
+    }
+    // Stable, so ties stay in path order
+    keyed.sort_by_key(|(key, _)| (key.is_none(), *key));
+    Ok(keyed.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Publish every markdown file in `dir`, recording each outcome in the
+/// directory's manifest as soon as it is known
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(dir = %dir.display())))]
+pub async fn publish_dir(
+    dir: &Path,
+    options: &PublishArgs,
+    client: &Client,
+    config: &ApiConfig,
+    json: bool,
+) -> Result<BatchSummary> {
+    let mut manifest = if options.resume {
+        Manifest::load(dir)?
+    } else {
+        Manifest::default()
+    };
+    let mut summary = BatchSummary::default();
+    let stop = stop_on_ctrl_c();
+    let files = sort_files(dir, markdown_files(dir)?, options.sort.unwrap_or(Sort::Name))?;
+    // With --json, stdout is left for the report
+    let progress = |line: String| {
+        if json {
+            errln!("{}", line);
+        } else {
+            outln!("{}", line);
+        }
+    };
+
+    for (index, file) in files.iter().enumerate() {
+        if stop.load(Ordering::SeqCst) {
+            summary.remaining = files[index..].to_vec();
+            break;
+        }
+        let file = file.clone();
+        if options.resume && manifest.succeeded(&file) {
+            summary.skipped.push(file);
+            continue;
+        }
+
+        if let Some(within) = options.modified_within {
+            let modified = std::fs::metadata(dir.join(&file))?.modified()?;
+            let recent = SystemTime::now()
+                .duration_since(modified)
+                .map_or(true, |age| age <= within);
+            if !recent {
+                progress(format!(
+                    "Skipped {} (not modified within {})",
+                    file.display(),
+                    humantime::format_duration(within)
+                ));
+                summary.skipped.push(file);
+                continue;
+            }
+        }
+
+        // Only the front matter and defaults decide, so includes run once, in
+        // `publish`; a file that can't be parsed is left to fail, and be recorded, there
+        let path = dir.join(&file);
+        let gated = parse_metadata(&path, &options.post.metadata, false)
+            .is_ok_and(|parsed| !parsed.metadata.publish.unwrap_or(!options.require_publish_flag));
+        if gated {
+            progress(format!("Skipped {} (not marked for publishing)", file.display()));
+            summary.skipped.push(file);
+            continue;
+        }
+
+        let published = publish(path, options, client, config);
+        let result = match options.timeout_per_file {
+            Some(seconds) => tokio::time::timeout(Duration::from_secs(seconds), published)
+                .await
+                .map_err(|_| seconds),
+            None => Ok(published.await),
+        };
+
+        let outcome = match result {
+            Err(seconds) => {
+                errln!("Timed out publishing {} after {}s", file.display(), seconds);
+                summary.failed.push(FailedFile {
+                    file: file.clone(),
+                    error: format!("timed out after {}s", seconds),
+                    timed_out: true,
+                });
+                Outcome::TimedOut { seconds }
+            }
+            Ok(Ok(HistoryEntry { url, .. })) => {
+                progress(format!("Published {} at {}", file.display(), url));
+                summary.succeeded.push(PublishedFile {
+                    file: file.clone(),
+                    url: url.clone(),
+                });
+                Outcome::Success { url }
+            }
+            Ok(Err(err)) => {
+                errln!("Failed to publish {}: {}", file.display(), err);
+                summary.failed.push(FailedFile {
+                    file: file.clone(),
+                    error: err.to_string(),
+                    timed_out: false,
+                });
+                Outcome::Failed {
+                    error: err.to_string(),
+                }
+            }
+        };
+
+        manifest.files.insert(file, outcome);
+        manifest.save(dir)?;
+    }
+
+    Ok(summary)
+}
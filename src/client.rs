@@ -0,0 +1,371 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER},
+    StatusCode,
+};
+use tokio::sync::Semaphore;
+use url::Url;
+
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+/// Times a request answered with 429 and a `Retry-After` is sent again
+const MAX_RATE_LIMIT_RETRIES: usize = 3;
+/// Times `send_rebuilt` tries again after a failure that may pass
+const MAX_REBUILT_RETRIES: u32 = 3;
+
+/// Networking options shared by every command that talks to Medium
+#[derive(clap::Args, Clone)]
+pub struct ClientOptions {
+    /// Override the User-Agent header sent with every request
+    #[arg(long, global = true)]
+    pub user_agent: Option<String>,
+    /// Keep at most this many idle connections per host [default: unlimited]
+    #[arg(long, global = true)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Allow at most this many requests in flight at once to any one host,
+    /// such as Medium's API [default: unlimited]. Directories are published
+    /// one file at a time, so this only limits the requests made for a
+    /// single post: its image uploads, retries and webhook.
+    #[arg(long, global = true)]
+    pub max_concurrency_per_host: Option<usize>,
+    /// Extra `Key: Value` header sent with every request, by `init` and
+    /// `publish` alike, e.g. for a proxy that needs its own credentials.
+    /// Repeat for more headers. The Medium token is always sent separately.
+    #[arg(long = "header", value_name = "KEY: VALUE", global = true, value_parser = parse_header)]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    /// Send requests through an http://, https:// or socks5:// proxy; a bare
+    /// `host:port` is taken as http://. Without it, HTTPS_PROXY, HTTP_PROXY
+    /// and NO_PROXY apply as usual.
+    #[arg(long, value_name = "URL", global = true, value_parser = parse_proxy)]
+    pub proxy: Option<Url>,
+    /// Print Medium's rate-limit headers after each request
+    #[arg(long, global = true)]
+    pub verbose: bool,
+    /// Longest wait for a rate limit to reset, or for a 429's `Retry-After`
+    /// to pass, before giving up instead so unattended runs can't hang
+    #[arg(long, value_name = "SECONDS", default_value_t = 120, global = true)]
+    pub max_retry_wait: u64,
+    /// Vary each retry's delay randomly by up to this percentage, so files
+    /// rate limited together don't all retry at once. A `Retry-After` is
+    /// only ever lengthened, and no delay goes past --max-retry-wait.
+    #[arg(long, value_name = "PCT", default_value_t = 20, global = true, value_parser = parse_percentage)]
+    pub retry_jitter: u32,
+}
+
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| "expected `Key: Value`".to_string())?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|err| err.to_string())?;
+    if name == AUTHORIZATION {
+        return Err("the Authorization header carries the Medium token and cannot be replaced".to_string());
+    }
+    let value = HeaderValue::from_str(value.trim()).map_err(|err| err.to_string())?;
+    Ok((name, value))
+}
+
+fn parse_percentage(percentage: &str) -> Result<u32, String> {
+    match percentage.trim_end_matches('%').parse() {
+        Ok(percentage) if percentage <= 100 => Ok(percentage),
+        _ => Err("expected a percentage from 0 to 100".to_string()),
+    }
+}
+
+fn parse_proxy(proxy: &str) -> Result<Url, String> {
+    // `host:port` would otherwise parse as a URL with the scheme `host`
+    let url = match proxy.contains("://") {
+        true => Url::parse(proxy),
+        false => Url::parse(&format!("http://{}", proxy)),
+    }
+    .map_err(|err| err.to_string())?;
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => Ok(url),
+        scheme => Err(format!("unsupported proxy scheme `{}`", scheme)),
+    }
+}
+
+impl ClientOptions {
+    /// Build a client, falling back to the config's User-Agent when none was given
+    pub fn build(&self, saved_user_agent: Option<&str>) -> Result<Client> {
+        let user_agent = self
+            .user_agent
+            .as_deref()
+            .or(saved_user_agent)
+            .unwrap_or(DEFAULT_USER_AGENT);
+
+        let headers: HeaderMap = self.headers.iter().cloned().collect();
+        let mut builder = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .default_headers(headers);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+        }
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+
+        Ok(Client {
+            inner: builder.build()?,
+            permits: self.max_concurrency_per_host.map(|limit| {
+                Arc::new(HostPermits {
+                    limit: limit.max(1),
+                    hosts: Mutex::default(),
+                })
+            }),
+            proxy: self.proxy.clone(),
+            verbose: self.verbose,
+            max_retry_wait: Duration::from_secs(self.max_retry_wait),
+            retry_jitter: self.retry_jitter,
+            paused_until: Arc::default(),
+        })
+    }
+}
+
+/// A --max-concurrency-per-host semaphore for each host requests go to
+struct HostPermits {
+    limit: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostPermits {
+    fn semaphore(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+            .clone()
+    }
+}
+
+/// A `reqwest::Client` that every Medium request is sent through
+#[derive(Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    permits: Option<Arc<HostPermits>>,
+    proxy: Option<Url>,
+    verbose: bool,
+    max_retry_wait: Duration,
+    /// Percentage retry delays are randomly varied by
+    retry_jitter: u32,
+    /// Set once the quota runs out so later requests wait for the reset
+    paused_until: Arc<Mutex<Option<Instant>>>,
+}
+
+/// Quota reported by `X-RateLimit-*` headers, when Medium sends them
+#[derive(Debug)]
+struct RateLimit {
+    limit: Option<u64>,
+    remaining: u64,
+    reset: Option<u64>,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<RateLimit> {
+        let number = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        };
+        Some(RateLimit {
+            limit: number("x-ratelimit-limit"),
+            remaining: number("x-ratelimit-remaining")?,
+            reset: number("x-ratelimit-reset"),
+        })
+    }
+
+    /// Time left until the quota resets. The header may hold either a Unix
+    /// timestamp or a number of seconds, so large values are read as the former.
+    fn reset_in(&self) -> Option<Duration> {
+        let reset = self.reset?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        if reset > 1_000_000_000 {
+            Some(Duration::from_secs(reset.saturating_sub(now)))
+        } else {
+            Some(Duration::from_secs(reset))
+        }
+    }
+}
+
+/// How long a 429 response asks us to wait. Only the number-of-seconds
+/// form of `Retry-After` is understood; an HTTP date is treated as absent.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let seconds = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// A random number from -1 to 1. `RandomState` is seeded randomly each
+/// time, which is all the randomness spreading out retries needs.
+fn random_unit() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    random as f64 / u64::MAX as f64 * 2.0 - 1.0
+}
+
+fn rate_limited(wait: Duration) -> anyhow::Error {
+    anyhow!(
+        "Rate limited for another {}s, longer than --max-retry-wait allows; retry later",
+        wait.as_secs()
+    )
+}
+
+impl Client {
+    /// `wait` varied by up to --retry-jitter percent, either way for our own
+    /// backoff but only longer when the server said how long to wait
+    fn jittered(&self, wait: Duration, server_given: bool) -> Duration {
+        let mut offset = random_unit() * f64::from(self.retry_jitter) / 100.0;
+        if server_given {
+            offset = offset.abs();
+        }
+        wait.mul_f64(1.0 + offset).min(self.max_retry_wait.max(wait))
+    }
+
+    pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.inner.get(url)
+    }
+
+    pub fn head(&self, url: &str) -> reqwest::RequestBuilder {
+        self.inner.head(url)
+    }
+
+    pub fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.inner.post(url)
+    }
+
+    pub async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let host = request
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .and_then(|request| request.url().host_str().map(str::to_string));
+        self.send_to(host.as_deref(), request).await
+    }
+
+    /// Send `request` to `host`, the host of its URL, which can't be read
+    /// back from a request that can't be copied
+    async fn send_to(&self, host: Option<&str>, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let _permit = match (&self.permits, host) {
+            (Some(permits), Some(host)) => Some(permits.semaphore(host).acquire_owned().await?),
+            _ => None,
+        };
+
+        let mut request = request;
+        let mut retries = 0;
+        loop {
+            let paused_until = *self.paused_until.lock().unwrap();
+            if let Some(until) = paused_until {
+                let wait = until.saturating_duration_since(Instant::now());
+                if wait > self.max_retry_wait {
+                    return Err(rate_limited(wait));
+                }
+                // Every request waiting on the reset would otherwise go at once
+                tokio::time::sleep(self.jittered(wait, true)).await;
+            }
+
+            // Multipart uploads can't be copied, so those are never retried
+            let retry = request.try_clone();
+            let response = request.send().await.map_err(|err| match &self.proxy {
+                Some(proxy) if err.is_connect() => {
+                    let message = format!("Could not connect through proxy {}: {}", proxy, err);
+                    anyhow::Error::from(err).context(message)
+                }
+                _ => err.into(),
+            })?;
+            if let Some(rate_limit) = RateLimit::from_headers(response.headers()) {
+                self.observe(&rate_limit);
+            }
+
+            match (retry_after(&response), retry) {
+                (Some(wait), _) if wait > self.max_retry_wait => return Err(rate_limited(wait)),
+                (Some(wait), Some(retry)) if retries < MAX_RATE_LIMIT_RETRIES => {
+                    let wait = self.jittered(wait, true);
+                    errln!("Rate limited; retrying in {}s", wait.as_secs());
+                    tokio::time::sleep(wait).await;
+                    request = retry;
+                    retries += 1;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+
+    /// Send a request that can't be copied, such as a multipart upload,
+    /// building it again for each attempt. Rate limits, server errors and
+    /// dropped connections are retried with a growing delay, so one flaky
+    /// upload doesn't fail the whole publish.
+    pub async fn send_rebuilt<F>(&self, build: F) -> Result<reqwest::Response>
+    where
+        F: Fn() -> Result<reqwest::RequestBuilder>,
+    {
+        let url = build()?.build()?.url().clone();
+        let mut attempt = 0;
+        loop {
+            let result = self.send_to(url.host_str(), build()?).await;
+            let backoff = Duration::from_secs(1 << attempt);
+            let wait = match &result {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => match retry_after(response) {
+                    Some(wait) => Some((wait, true)),
+                    None => Some((backoff, false)),
+                },
+                Ok(response) if response.status().is_server_error() => Some((backoff, false)),
+                Ok(_) => None,
+                Err(err) => err
+                    .downcast_ref::<reqwest::Error>()
+                    .filter(|err| err.is_connect() || err.is_timeout())
+                    .map(|_| (backoff, false)),
+            };
+            match wait {
+                Some((wait, server_given)) if attempt < MAX_REBUILT_RETRIES && wait <= self.max_retry_wait => {
+                    let wait = self.jittered(wait, server_given);
+                    errln!("Request failed; retrying in {}s", wait.as_secs());
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    fn observe(&self, rate_limit: &RateLimit) {
+        if self.verbose {
+            match rate_limit.limit {
+                Some(limit) => errln!("Rate limit: {}/{} requests remaining", rate_limit.remaining, limit),
+                None => errln!("Rate limit: {} requests remaining", rate_limit.remaining),
+            }
+        }
+
+        if rate_limit.remaining == 0 {
+            if let Some(wait) = rate_limit.reset_in() {
+                // A longer wait makes the next request fail instead
+                if wait <= self.max_retry_wait {
+                    errln!("Rate limit reached; pausing {}s until it resets", wait.as_secs());
+                }
+                *self.paused_until.lock().unwrap() = Some(Instant::now() + wait);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_kept_per_host() {
+        let permits = HostPermits {
+            limit: 2,
+            hosts: Mutex::default(),
+        };
+        let medium = permits.semaphore("api.medium.com");
+        let _taken = medium.clone().try_acquire_many_owned(2).unwrap();
+        assert_eq!(permits.semaphore("api.medium.com").available_permits(), 0);
+        assert_eq!(permits.semaphore("hooks.example.com").available_permits(), 2);
+    }
+}
@@ -0,0 +1,38 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use termcolor::{Ansi, Color, ColorSpec, WriteColor};
+
+static STDOUT: AtomicBool = AtomicBool::new(false);
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Color each stream only when it's a terminal, and never with --no-color,
+/// a non-empty NO_COLOR or --json, whose output is meant for programs
+pub fn init(no_color: bool, json: bool) {
+    let no_color = no_color || json || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    STDOUT.store(!no_color && std::io::stdout().is_terminal(), Ordering::Relaxed);
+    STDERR.store(!no_color && std::io::stderr().is_terminal(), Ordering::Relaxed);
+}
+
+fn paint(text: &str, color: Color, enabled: &AtomicBool) -> String {
+    if !enabled.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    let mut out = Ansi::new(Vec::new());
+    let _ = out.set_color(ColorSpec::new().set_fg(Some(color)).set_bold(true));
+    let _ = out.write_all(text.as_bytes());
+    let _ = out.reset();
+    String::from_utf8(out.into_inner()).unwrap_or_else(|_| text.to_string())
+}
+
+/// `text` in green, for printing to stdout
+pub fn success(text: &str) -> String {
+    paint(text, Color::Green, &STDOUT)
+}
+
+/// `text` in red, for printing to stderr
+pub fn error(text: &str) -> String {
+    paint(text, Color::Red, &STDERR)
+}
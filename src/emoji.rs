@@ -0,0 +1,308 @@
+use std::collections::BTreeSet;
+
+use regex::Regex;
+
+use crate::transform;
+
+/// GitHub shortcodes and the emoji they stand for, sorted by shortcode
+/// for binary search. The common ones rather than all of GitHub's.
+const SHORTCODES: [(&str, &str); 229] = [
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("1st_place_medal", "🥇"),
+    ("airplane", "✈️"),
+    ("alarm_clock", "⏰"),
+    ("alien", "👽"),
+    ("apple", "🍎"),
+    ("arrow_down", "⬇️"),
+    ("arrow_left", "⬅️"),
+    ("arrow_right", "➡️"),
+    ("arrow_up", "⬆️"),
+    ("arrows_counterclockwise", "🔄"),
+    ("art", "🎨"),
+    ("back", "🔙"),
+    ("balloon", "🎈"),
+    ("bar_chart", "📊"),
+    ("battery", "🔋"),
+    ("bee", "🐝"),
+    ("beer", "🍺"),
+    ("bell", "🔔"),
+    ("bike", "🚲"),
+    ("black_heart", "🖤"),
+    ("blue_heart", "💙"),
+    ("blush", "😊"),
+    ("book", "📖"),
+    ("bookmark", "🔖"),
+    ("books", "📚"),
+    ("boom", "💥"),
+    ("brain", "🧠"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("bulb", "💡"),
+    ("cactus", "🌵"),
+    ("cake", "🍰"),
+    ("calendar", "📆"),
+    ("camera", "📷"),
+    ("candle", "🕯️"),
+    ("car", "🚗"),
+    ("cat", "🐱"),
+    ("chart_with_downwards_trend", "📉"),
+    ("chart_with_upwards_trend", "📈"),
+    ("checkered_flag", "🏁"),
+    ("clap", "👏"),
+    ("clock1", "🕐"),
+    ("cloud", "☁️"),
+    ("coffee", "☕"),
+    ("computer", "💻"),
+    ("confetti_ball", "🎊"),
+    ("confused", "😕"),
+    ("construction", "🚧"),
+    ("cool", "🆒"),
+    ("crab", "🦀"),
+    ("credit_card", "💳"),
+    ("crossed_fingers", "🤞"),
+    ("crown", "👑"),
+    ("cry", "😢"),
+    ("dart", "🎯"),
+    ("dizzy", "💫"),
+    ("dog", "🐶"),
+    ("dollar", "💵"),
+    ("droplet", "💧"),
+    ("earth_africa", "🌍"),
+    ("earth_americas", "🌎"),
+    ("electric_plug", "🔌"),
+    ("email", "📧"),
+    ("end", "🔚"),
+    ("envelope", "✉️"),
+    ("evergreen_tree", "🌲"),
+    ("exclamation", "❗"),
+    ("expressionless", "😑"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fire", "🔥"),
+    ("flashlight", "🔦"),
+    ("floppy_disk", "💾"),
+    ("flushed", "😳"),
+    ("fox_face", "🦊"),
+    ("free", "🆓"),
+    ("game_die", "🎲"),
+    ("gear", "⚙️"),
+    ("gem", "💎"),
+    ("ghost", "👻"),
+    ("gift", "🎁"),
+    ("globe_with_meridians", "🌐"),
+    ("green_heart", "💚"),
+    ("grinning", "😀"),
+    ("hammer", "🔨"),
+    ("handshake", "🤝"),
+    ("hash", "#️⃣"),
+    ("headphones", "🎧"),
+    ("hear_no_evil", "🙉"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("heavy_check_mark", "✔️"),
+    ("heavy_minus_sign", "➖"),
+    ("heavy_plus_sign", "➕"),
+    ("hourglass", "⌛"),
+    ("house", "🏠"),
+    ("hugs", "🤗"),
+    ("hushed", "😯"),
+    ("inbox_tray", "📥"),
+    ("information_source", "ℹ️"),
+    ("innocent", "😇"),
+    ("iphone", "📱"),
+    ("joy", "😂"),
+    ("key", "🔑"),
+    ("keyboard", "⌨️"),
+    ("kiss", "💋"),
+    ("laughing", "😆"),
+    ("link", "🔗"),
+    ("lock", "🔒"),
+    ("loudspeaker", "📢"),
+    ("mag", "🔍"),
+    ("mailbox", "📫"),
+    ("man_technologist", "👨‍💻"),
+    ("mask", "😷"),
+    ("medal_sports", "🏅"),
+    ("mega", "📣"),
+    ("memo", "📝"),
+    ("microscope", "🔬"),
+    ("money_with_wings", "💸"),
+    ("moneybag", "💰"),
+    ("movie_camera", "🎥"),
+    ("muscle", "💪"),
+    ("musical_note", "🎵"),
+    ("nerd_face", "🤓"),
+    ("neutral_face", "😐"),
+    ("new", "🆕"),
+    ("no_entry", "⛔"),
+    ("ocean", "🌊"),
+    ("octopus", "🐙"),
+    ("office", "🏢"),
+    ("ok", "🆗"),
+    ("ok_hand", "👌"),
+    ("on", "🔛"),
+    ("orange_heart", "🧡"),
+    ("outbox_tray", "📤"),
+    ("owl", "🦉"),
+    ("package", "📦"),
+    ("palm_tree", "🌴"),
+    ("panda_face", "🐼"),
+    ("paperclip", "📎"),
+    ("partying_face", "🥳"),
+    ("pencil2", "✏️"),
+    ("penguin", "🐧"),
+    ("pizza", "🍕"),
+    ("point_down", "👇"),
+    ("point_left", "👈"),
+    ("point_right", "👉"),
+    ("point_up", "☝️"),
+    ("poop", "💩"),
+    ("pray", "🙏"),
+    ("purple_heart", "💜"),
+    ("pushpin", "📌"),
+    ("question", "❓"),
+    ("rabbit", "🐰"),
+    ("rage", "😡"),
+    ("rainbow", "🌈"),
+    ("raised_hands", "🙌"),
+    ("recycle", "♻️"),
+    ("relieved", "😌"),
+    ("robot", "🤖"),
+    ("rocket", "🚀"),
+    ("rose", "🌹"),
+    ("rotating_light", "🚨"),
+    ("satellite", "📡"),
+    ("school", "🏫"),
+    ("scientist", "🧑‍🔬"),
+    ("scream", "😱"),
+    ("see_no_evil", "🙈"),
+    ("seedling", "🌱"),
+    ("shield", "🛡️"),
+    ("ship", "🚢"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smirk", "😏"),
+    ("snake", "🐍"),
+    ("snowflake", "❄️"),
+    ("sob", "😭"),
+    ("soon", "🔜"),
+    ("sparkles", "✨"),
+    ("sparkling_heart", "💖"),
+    ("speak_no_evil", "🙊"),
+    ("speech_balloon", "💬"),
+    ("star", "⭐"),
+    ("star2", "🌟"),
+    ("star_struck", "🤩"),
+    ("sunglasses", "😎"),
+    ("sunny", "☀️"),
+    ("sweat_drops", "💦"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("teacher", "🧑‍🏫"),
+    ("telescope", "🔭"),
+    ("test_tube", "🧪"),
+    ("thinking", "🤔"),
+    ("thought_balloon", "💭"),
+    ("top", "🔝"),
+    ("train", "🚆"),
+    ("triangular_flag_on_post", "🚩"),
+    ("trophy", "🏆"),
+    ("tulip", "🌷"),
+    ("turtle", "🐢"),
+    ("two_hearts", "💕"),
+    ("umbrella", "☔"),
+    ("unicorn", "🦄"),
+    ("unlock", "🔓"),
+    ("upside_down_face", "🙃"),
+    ("v", "✌️"),
+    ("video_game", "🎮"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("whale", "🐳"),
+    ("white_check_mark", "✅"),
+    ("white_flag", "🏳️"),
+    ("wink", "😉"),
+    ("woman_technologist", "👩‍💻"),
+    ("wrench", "🔧"),
+    ("writing_hand", "✍️"),
+    ("x", "❌"),
+    ("yawning_face", "🥱"),
+    ("yellow_heart", "💛"),
+    ("zany_face", "🤪"),
+    ("zap", "⚡"),
+];
+
+/// The emoji for a shortcode such as `rocket`, without the colons
+pub fn lookup(shortcode: &str) -> Option<&'static str> {
+    SHORTCODES
+        .binary_search_by_key(&shortcode, |(name, _)| name)
+        .ok()
+        .map(|index| SHORTCODES[index].1)
+}
+
+/// Replace `:rocket:` style shortcodes outside of code with emoji,
+/// returning the new content and the shortcodes that weren't recognised,
+/// which are left as written
+pub fn emojify(content: &str, content_format: &str) -> (String, Vec<String>) {
+    let shortcode = Regex::new(r":([a-z0-9_+-]*[a-z][a-z0-9_+-]*|[+-]1|100):").unwrap();
+    let code = if content_format == "html" {
+        Vec::new()
+    } else {
+        transform::code_ranges(content)
+    };
+
+    let mut output = String::with_capacity(content.len());
+    let mut unknown = BTreeSet::new();
+    let mut last = 0;
+    for captures in shortcode.captures_iter(content) {
+        let whole = captures.get(0).unwrap();
+        if code.iter().any(|range| range.contains(&whole.start())) {
+            continue;
+        }
+        match lookup(&captures[1]) {
+            Some(emoji) => {
+                output.push_str(&content[last..whole.start()]);
+                output.push_str(emoji);
+                last = whole.end();
+            }
+            None => {
+                unknown.insert(captures[1].to_string());
+            }
+        }
+    }
+    output.push_str(&content[last..]);
+    (output, unknown.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcodes_are_sorted_for_binary_search() {
+        assert!(SHORTCODES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(lookup("rocket"), Some("🚀"));
+        assert_eq!(lookup("+1"), Some("👍"));
+        assert_eq!(lookup("Rocket"), None);
+    }
+
+    #[test]
+    fn shortcodes_become_emoji_outside_code() {
+        let (output, unknown) = emojify(":rocket: at 10:30:45 `:bug:`\n\n```\n:zap:\n```\n:100: :nope: :nope:", "markdown");
+        assert_eq!(output, "🚀 at 10:30:45 `:bug:`\n\n```\n:zap:\n```\n💯 :nope: :nope:");
+        assert_eq!(unknown, ["nope"]);
+    }
+
+    #[test]
+    fn html_has_no_code_spans_to_skip() {
+        let (output, unknown) = emojify("<code>:bug:</code>", "html");
+        assert_eq!(output, "<code>🐛</code>");
+        assert!(unknown.is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+
+use crate::{csv_row, PublishStatus, Series};
+
+const FILE_NAME: &str = ".markmedium_history";
+const DRAFTS_FILE_NAME: &str = ".markmedium_drafts";
+const SNAPSHOT_DIR_NAME: &str = ".markmedium_snapshots";
+const PENDING_DIR_NAME: &str = ".markmedium_pending";
+
+/// A single published post, stored one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub file: PathBuf,
+    #[serde(default)]
+    pub slug: String,
+    pub title: String,
+    /// Author the post was published as, which `--author-id` can change
+    #[serde(default)]
+    pub author_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub url: String,
+    pub status: PublishStatus,
+    pub published_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series: Option<Series>,
+    /// Old URLs from the front matter's `aliases`, to redirect to `url`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        file: PathBuf,
+        slug: String,
+        title: String,
+        author_id: String,
+        id: Option<String>,
+        url: String,
+        status: PublishStatus,
+    ) -> Self {
+        HistoryEntry {
+            file,
+            slug,
+            title,
+            author_id,
+            id,
+            url,
+            status,
+            published_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            series: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    /// How long ago the entry was recorded, if the timestamp is readable
+    pub fn age(&self) -> Option<Duration> {
+        let published_at = humantime::parse_rfc3339(&self.published_at).ok()?;
+        SystemTime::now().duration_since(published_at).ok()
+    }
+}
+
+fn history_path() -> PathBuf {
+    home_dir().unwrap().join(FILE_NAME)
+}
+
+fn drafts_path() -> PathBuf {
+    home_dir().unwrap().join(DRAFTS_FILE_NAME)
+}
+
+fn append(path: PathBuf, entry: &HistoryEntry) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+fn read(path: PathBuf) -> Result<Vec<HistoryEntry>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Append to the history, and to the separate drafts log for drafts
+pub fn record(entry: &HistoryEntry) -> Result<()> {
+    append(history_path(), entry)?;
+    if matches!(entry.status, PublishStatus::Draft) {
+        append(drafts_path(), entry)?;
+    }
+    Ok(())
+}
+
+pub fn read_entries() -> Result<Vec<HistoryEntry>> {
+    read(history_path())
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// An array of every recorded field
+    Json,
+    /// A header row and one row per entry, for spreadsheets
+    Csv,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Format::Table => "table",
+            Format::Json => "json",
+            Format::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub fn format_entries(entries: &[HistoryEntry], format: &Format) -> Result<String> {
+    let rows = entries.iter().map(|entry| {
+        [
+            entry.published_at.clone(),
+            entry.status.to_string(),
+            entry.title.clone(),
+            entry.url.clone(),
+            entry.file.to_string_lossy().into_owned(),
+        ]
+    });
+
+    Ok(match format {
+        Format::Json => serde_json::to_string_pretty(entries)? + "\n",
+        Format::Csv => {
+            let header = ["published_at", "status", "title", "url", "file"].map(String::from);
+            std::iter::once(header).chain(rows).map(|row| csv_row(&row)).collect()
+        }
+        Format::Table => {
+            let rows: Vec<_> = rows.collect();
+            let width = |column: usize| {
+                rows.iter()
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            };
+            let (status_width, title_width) = (width(1), width(2));
+            rows.iter()
+                .map(|row| {
+                    format!(
+                        "{}  {:status_width$}  {:title_width$}  {}\n",
+                        row[0], row[1], row[2], row[3]
+                    )
+                })
+                .collect()
+        }
+    })
+}
+
+/// The latest published entry for each part of the series `name`, by index
+pub fn series_parts(name: &str) -> Result<Vec<(Series, HistoryEntry)>> {
+    let mut parts = BTreeMap::new();
+    for entry in read_entries()? {
+        if let Some(series) = entry.series.clone().filter(|series| series.name == name) {
+            parts.insert(series.index, (series, entry));
+        }
+    }
+    Ok(parts.into_values().collect())
+}
+
+/// Drafts by `author_id` recorded at least `older_than` ago, oldest first
+pub fn stale_drafts(author_id: &str, older_than: Duration) -> Result<Vec<HistoryEntry>> {
+    let drafts = read(drafts_path())?
+        .into_iter()
+        .filter(|entry| entry.author_id == author_id)
+        // Entries whose timestamp was edited into something unreadable have no age to compare
+        .filter(|entry| entry.age().is_some_and(|age| age >= older_than))
+        .collect();
+    Ok(drafts)
+}
+
+/// The most recent draft published from `target`, a file or the draft's URL
+pub fn latest_draft(target: &str) -> Result<Option<HistoryEntry>> {
+    let file = std::fs::canonicalize(target).ok();
+    let draft = read(drafts_path())?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.url == target || file.as_ref() == Some(&entry.file));
+    Ok(draft)
+}
+
+/// 64-bit FNV-1a of `bytes`. Files are named by it, so unlike `DefaultHasher`
+/// it must give the same value whichever Rust version built markmedium.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn snapshot_path(file: &Path) -> PathBuf {
+    home_dir()
+        .unwrap()
+        .join(SNAPSHOT_DIR_NAME)
+        .join(format!("{:016x}.md", stable_hash(file.as_os_str().as_encoded_bytes())))
+}
+
+/// Keep the content last published from `file`, for `publish --diff`
+pub fn save_snapshot(file: &Path, content: &str) -> Result<()> {
+    let path = snapshot_path(file);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+pub fn last_snapshot(file: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(snapshot_path(file)) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Marker for a publish request that was sent but whose outcome is unknown.
+/// Medium's API has no idempotency keys, so a post may exist even though we
+/// never saw the response, and sending the same request again would duplicate it.
+fn pending_path(request: &str) -> PathBuf {
+    // Tests mustn't leave markers in the real home directory
+    let dir = if cfg!(test) {
+        std::env::temp_dir().join("markmedium-pending-tests")
+    } else {
+        home_dir().unwrap().join(PENDING_DIR_NAME)
+    };
+    dir.join(format!("{:016x}", stable_hash(request.as_bytes())))
+}
+
+/// Note that `request` is about to be sent, returning whether an identical
+/// request was sent before without its outcome being known
+pub fn begin_attempt(request: &str) -> Result<bool> {
+    let path = pending_path(request);
+    if path.exists() {
+        return Ok(true);
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, humantime::format_rfc3339_seconds(SystemTime::now()).to_string())?;
+    Ok(false)
+}
+
+/// Forget `request` once its outcome is known either way
+pub fn finish_attempt(request: &str) -> Result<()> {
+    match std::fs::remove_file(pending_path(request)) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_hash_is_fnv_1a() {
+        assert_eq!(stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(stable_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn unfinished_attempts_are_remembered() {
+        let request = "https://api.medium.com/v1/users/me/posts\n{\"title\":\"unfinished\"}";
+        assert!(!begin_attempt(request).unwrap());
+        assert!(begin_attempt(request).unwrap());
+        assert!(!begin_attempt("another request").unwrap());
+        finish_attempt(request).unwrap();
+        finish_attempt(request).unwrap();
+        finish_attempt("another request").unwrap();
+        assert!(!begin_attempt(request).unwrap());
+        finish_attempt(request).unwrap();
+    }
+}
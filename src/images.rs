@@ -0,0 +1,280 @@
+use std::{collections::HashMap, ops::Range, path::Path};
+
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{client::Client, read_response};
+
+#[derive(Deserialize)]
+struct UploadedImage {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct ImageResponse {
+    data: UploadedImage,
+}
+
+/// An inline markdown image, `![alt](src "title")`
+#[derive(Debug)]
+pub struct ImageLink {
+    pub src: String,
+    /// Byte range of `src` within the content
+    src_range: Range<usize>,
+    /// Byte range of the whole image
+    range: Range<usize>,
+}
+
+/// Position of the destination inside `![alt](dest "title")`, skipping a
+/// bracketed alt text that may itself contain brackets
+fn destination_range(source: &str) -> Option<Range<usize>> {
+    let bytes = source.as_bytes();
+    if !source.starts_with("![") || !source.ends_with(')') {
+        // Reference-style images keep their URL elsewhere
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut escaped = false;
+    let mut alt_end = None;
+    for (index, byte) in bytes.iter().enumerate().skip(1) {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match byte {
+            b'\\' => escaped = true,
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    alt_end = Some(index);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let open = alt_end? + 1;
+    if bytes.get(open) != Some(&b'(') {
+        return None;
+    }
+
+    let rest = &source[open + 1..source.len() - 1];
+    let leading = rest.len() - rest.trim_start().len();
+    let start = open + 1 + leading;
+    let rest = rest.trim_start();
+
+    if let Some(stripped) = rest.strip_prefix('<') {
+        let end = stripped.find('>')?;
+        return Some(start + 1..start + 1 + end);
+    }
+
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.trim_end().len());
+    Some(start..start + end)
+}
+
+/// Every inline image outside of code blocks and spans
+pub fn image_links(content: &str) -> Vec<ImageLink> {
+    let mut links = Vec::new();
+    let mut current: Option<(String, Range<usize>, Range<usize>)> = None;
+
+    for (event, range) in Parser::new(content).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                current = destination_range(&content[range.clone()])
+                    .map(|dest| (dest_url.to_string(), range.start + dest.start..range.start + dest.end, range));
+            }
+            Event::End(pulldown_cmark::TagEnd::Image) => {
+                if let Some((src, src_range, range)) = current.take() {
+                    links.push(ImageLink { src, src_range, range });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    links
+}
+
+/// Whether an image source points at a file rather than a URL
+pub fn is_local(src: &str) -> bool {
+    !src.starts_with("//") && Url::parse(src).is_err()
+}
+
+/// Sources of images loaded over plain `http://`, each listed once
+pub fn insecure_images(content: &str) -> Vec<String> {
+    let mut sources: Vec<String> = Vec::new();
+    for link in image_links(content) {
+        let insecure = link.src.get(..7).is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://"));
+        if insecure && !sources.contains(&link.src) {
+            sources.push(link.src);
+        }
+    }
+    sources
+}
+
+/// Replace every whole image with source `src`, alt text and all, by `text`
+fn replace_image(content: &str, src: &str, text: &str) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut last = 0;
+
+    for link in image_links(content).into_iter().filter(|link| link.src == src) {
+        output.push_str(&content[last..link.range.start]);
+        output.push_str(text);
+        last = link.range.end;
+    }
+
+    output.push_str(&content[last..]);
+    output
+}
+
+/// Swap image sources found in `replacements`, leaving the `![alt]` and
+/// title portions untouched so captions survive
+pub fn replace_sources(content: &str, replacements: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut last = 0;
+
+    for link in image_links(content) {
+        if let Some(url) = replacements.get(&link.src) {
+            output.push_str(&content[last..link.src_range.start]);
+            output.push_str(url);
+            last = link.src_range.end;
+        }
+    }
+
+    output.push_str(&content[last..]);
+    output
+}
+
+fn mime_type(path: &Path) -> Result<&'static str> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("jpg") | Some("jpeg") => Ok("image/jpeg"),
+        Some("png") => Ok("image/png"),
+        Some("gif") => Ok("image/gif"),
+        Some("tif") | Some("tiff") => Ok("image/tiff"),
+        _ => Err(anyhow!("Unsupported image type: {}", path.display())),
+    }
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(client, token)))]
+pub async fn upload_image(client: &Client, token: &str, path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path).map_err(|err| anyhow!("Could not read image {}: {}", path.display(), err))?;
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mime_type = mime_type(path)?;
+
+    let response: reqwest::Response = client
+        .send_rebuilt(|| {
+            let part = reqwest::multipart::Part::bytes(bytes.clone())
+                .file_name(file_name.clone())
+                .mime_str(mime_type)?;
+            Ok(client
+                .post("https://api.medium.com/v1/images")
+                .bearer_auth(token)
+                .multipart(reqwest::multipart::Form::new().part("image", part)))
+        })
+        .await?;
+
+    let image_response: ImageResponse = read_response(response).await?;
+    Ok(image_response.data.url)
+}
+
+/// Upload every local image referenced by `content`, resolving paths
+/// relative to `base_dir`, and point the markdown at the uploaded copies.
+/// Every image is tried before failing, so one run names all the broken
+/// ones; with `skip_failed` those become placeholder text instead.
+pub async fn upload_local_images(
+    client: &Client,
+    token: &str,
+    base_dir: &Path,
+    content: &str,
+    skip_failed: bool,
+) -> Result<String> {
+    let mut replacements = HashMap::new();
+    let mut failures = Vec::new();
+
+    for link in image_links(content) {
+        let seen = replacements.contains_key(&link.src) || failures.iter().any(|(src, _)| *src == link.src);
+        if !is_local(&link.src) || seen {
+            continue;
+        }
+        match upload_image(client, token, &base_dir.join(&link.src)).await {
+            Ok(url) => {
+                errln!("Uploaded image {}", link.src);
+                replacements.insert(link.src, url);
+            }
+            Err(err) => failures.push((link.src, err)),
+        }
+    }
+
+    if !failures.is_empty() && !skip_failed {
+        let failures: Vec<String> = failures.iter().map(|(src, err)| format!("\n  {}: {}", src, err)).collect();
+        return Err(anyhow!(
+            "Could not upload {} of {} images; fix them or pass --skip-failed-images:{}",
+            failures.len(),
+            failures.len() + replacements.len(),
+            failures.concat()
+        ));
+    }
+
+    let mut content = replace_sources(content, &replacements);
+    for (src, err) in failures {
+        errln!("Warning: could not upload image {}, left a placeholder: {}", src, err);
+        content = replace_image(&content, &src, &format!("*[image unavailable: {}]*", src));
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replacements(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(src, url)| (src.to_string(), url.to_string())).collect()
+    }
+
+    #[test]
+    fn replacing_keeps_alt_text_and_title() {
+        let content = "![A [nested] caption](img/a.png \"The title\") and ![b](<img/b c.png>)";
+        let replaced = replace_sources(
+            content,
+            &replacements(&[("img/a.png", "https://cdn/a.png"), ("img/b c.png", "https://cdn/b.png")]),
+        );
+        assert_eq!(
+            replaced,
+            "![A [nested] caption](https://cdn/a.png \"The title\") and ![b](<https://cdn/b.png>)"
+        );
+    }
+
+    #[test]
+    fn code_and_reference_images_are_left_alone() {
+        let content = "`![a](a.png)`\n\n```\n![a](a.png)\n```\n\n![ref][a]\n\n[a]: a.png\n";
+        assert!(image_links(content).is_empty());
+        assert_eq!(replace_sources(content, &replacements(&[("a.png", "https://cdn/a.png")])), content);
+    }
+
+    #[test]
+    fn only_files_are_local() {
+        assert!(is_local("img/a.png"));
+        assert!(is_local("../a.png"));
+        assert!(!is_local("https://example.com/a.png"));
+        assert!(!is_local("//example.com/a.png"));
+        assert!(!is_local("data:image/png;base64,AAAA"));
+    }
+
+    #[test]
+    fn failed_images_become_placeholders() {
+        let content = "Before ![alt](a.png \"t\") after";
+        assert_eq!(replace_image(content, "a.png", "*[image unavailable]*"), "Before *[image unavailable]* after");
+    }
+}
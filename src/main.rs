@@ -1,13 +1,81 @@
-use std::{path::PathBuf, fmt};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    io::{IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
 use url::Url;
 
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand, ValueEnum};
 use dirs::home_dir;
-use serde::{Deserialize, Serialize};
+use encoding_rs::Encoding;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use yaml_front_matter::{Document, YamlFrontMatter};
 
+use client::{Client, ClientOptions};
+use transform::TransformOptions;
+
+// Console output that is also written to the --log-file. Everything the
+// user should see goes through these rather than the std macros.
+macro_rules! out {
+    ($($arg:tt)*) => {{
+        let text = format!($($arg)*);
+        std::print!("{}", text);
+        $crate::tee::write(&text);
+    }};
+}
+
+macro_rules! outln {
+    ($($arg:tt)*) => {{
+        out!("{}\n", format_args!($($arg)*))
+    }};
+}
+
+macro_rules! err {
+    ($($arg:tt)*) => {{
+        let text = format!($($arg)*);
+        std::eprint!("{}", text);
+        $crate::tee::write(&text);
+    }};
+}
+
+macro_rules! errln {
+    ($($arg:tt)*) => {{
+        err!("{}\n", format_args!($($arg)*))
+    }};
+}
+
+mod batch;
+mod client;
+mod color;
+mod emoji;
+mod history;
+mod images;
+mod metrics;
+mod migrate;
+mod schema;
+mod tee;
+mod transform;
+mod validate;
+mod watch;
+mod webhook;
+
 const FILE_NAME: &str = ".markmedium";
+/// Used instead of `FILE_NAME` when it's the only config in the home directory
+const TOML_FILE_NAME: &str = ".markmedium.toml";
+const TOKEN_SETTINGS_URL: &str = "https://medium.com/me/settings/security";
+/// Credentials that, when both set, are used instead of the saved config
+const TOKEN_ENV: &str = "MEDIUM_TOKEN";
+const AUTHOR_ID_ENV: &str = "MEDIUM_AUTHOR_ID";
+/// Name of the account saved by `init` without --profile
+const DEFAULT_PROFILE: &str = "default";
+/// Tokens older than this get a reminder that they may need regenerating
+const TOKEN_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
 
 /// Publish Medium articles from markdown content
 #[derive(Parser)]
@@ -15,19 +83,513 @@ const FILE_NAME: &str = ".markmedium";
 struct Args {
     #[command(subcommand)]
     command: Option<Commands>,
+    #[command(flatten)]
+    client: ClientOptions,
+    /// Print machine-readable JSON where supported
+    #[arg(long, global = true)]
+    json: bool,
+    /// Print without color, which is also left out when NO_COLOR is set or
+    /// output isn't a terminal
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Config file to use instead of ~/.markmedium, read and written as TOML
+    /// when it ends in `.toml` and JSON otherwise
+    #[arg(long, global = true, value_name = "PATH", env = "MARKMEDIUM_CONFIG")]
+    config: Option<PathBuf>,
+    /// Also append everything printed to this file, with timestamps and
+    /// the token left out
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+    /// Log level (error, warn, info, debug, trace), overriding RUST_LOG
+    #[cfg(feature = "tracing")]
+    #[arg(long, global = true)]
+    log_level: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Set up with your integration token
-    Init { token: String },
+    ///
+    /// The token is read from --token-file, the argument or MEDIUM_TOKEN,
+    /// in that order. A file or the variable keep it out of the process
+    /// list, where other users of the machine could see it.
+    Init {
+        #[arg(conflicts_with = "token_file")]
+        token: Option<String>,
+        /// Read the token from this file, such as a mounted secret
+        #[arg(long, value_name = "PATH")]
+        token_file: Option<PathBuf>,
+        /// Save the token without checking it against Medium, for offline setup
+        #[arg(long, requires = "id")]
+        no_verify: bool,
+        /// Author ID to save with --no-verify instead of looking it up
+        #[arg(long, requires = "no_verify", value_parser = non_empty)]
+        id: Option<String>,
+        /// Save the token as another account under this name, for
+        /// `publish --profiles`, keeping the default one
+        #[arg(long, value_parser = non_empty)]
+        profile: Option<String>,
+    },
     /// Publish markdown content on your Medium blog
-    Publish { file: PathBuf },
+    ///
+    /// When MEDIUM_TOKEN and MEDIUM_AUTHOR_ID are both set they are used
+    /// instead of the config saved by `init`, which is then never read, so
+    /// CI jobs can publish without running `init` first.
+    Publish(Box<PublishArgs>),
+    /// Publish as a draft for review, recording its URL in the history so
+    /// `promote` can publish it for real once it's approved
+    Draft(Box<PublishArgs>),
+    /// Publish publicly a file saved with `draft`, given in place of FILE
+    /// either as the file or as the draft's URL
+    ///
+    /// Medium's API can't change a post once it exists, so this publishes a
+    /// new copy from the file and the draft stays in your account until you
+    /// delete it on Medium.
+    Promote(Box<PublishArgs>),
+    /// Render markdown content to HTML and open it in the browser
+    Preview {
+        file: PathBuf,
+        #[command(flatten)]
+        post: PostOptions,
+    },
+    /// Write the assembled post without publishing it
+    Export {
+        /// Markdown file, or a directory to export every markdown file in it
+        file: PathBuf,
+        /// Directory to write into, mirroring the input's layout; required
+        /// for directories, a single file is printed without it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        post: PostOptions,
+    },
+    /// Write a single post as Medium-ready markdown for its manual import,
+    /// applying every transformation but publishing and uploading nothing.
+    /// Local images are kept as they are and listed, since the import can't
+    /// reach them.
+    Compile {
+        file: PathBuf,
+        /// File to write instead of printing the result
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        post: PostOptions,
+    },
+    /// Check the saved token against Medium
+    Status,
+    /// Verify the config and connectivity, exiting non-zero on failure
+    Check,
+    /// Manage the saved configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Show the published parts of a series
+    Series {
+        #[command(subcommand)]
+        action: SeriesAction,
+    },
+    /// List drafts recorded in your publishing history
+    Drafts {
+        /// Only show drafts created at least this many days ago
+        #[arg(long, default_value_t = 0)]
+        older_than: u64,
+        /// Show drafts published as this author instead of the one saved by `init`
+        #[arg(long, value_parser = non_empty)]
+        author_id: Option<String>,
+    },
+    /// List everything recorded in your publishing history
+    History {
+        #[arg(long, value_enum, default_value_t = history::Format::Table)]
+        format: history::Format,
+    },
+    /// Print the metadata parsed from a file's front matter as JSON, with
+    /// the byte offset where the body starts for mapping problems back to it
+    Inspect {
+        /// Markdown file, or `-` for stdin
+        file: PathBuf,
+        /// Print a table of each field's value and whether a flag, the front
+        /// matter or a default set it, instead of JSON
+        #[arg(long)]
+        show_sources: bool,
+        #[command(flatten)]
+        metadata: MetadataOptions,
+    },
+    /// Report markdown that won't render as intended, such as unclosed code
+    /// fences, malformed tables and broken link syntax
+    Lint {
+        /// Markdown file, or `-` for stdin
+        file: PathBuf,
+        /// Character encoding of the file, for older files that aren't UTF-8
+        #[arg(long, value_name = "NAME", value_parser = parse_encoding)]
+        encoding: Option<&'static Encoding>,
+    },
+    /// Import every post in a Jekyll `_posts` or Hugo content folder
+    Migrate {
+        dir: PathBuf,
+        /// Your site's address, so each `permalink` becomes the canonical URL
+        #[arg(long, value_name = "URL")]
+        site_url: Option<url::Url>,
+        /// Publish status for the imported posts
+        #[arg(long, value_enum, default_value_t = PublishStatus::Draft)]
+        status: PublishStatus,
+        /// Write where every post ended up, as CSV for a `.csv` path or JSON otherwise
+        #[arg(long, value_name = "PATH")]
+        report: Option<PathBuf>,
+    },
+    /// List recent posts from your public RSS feed, since the API can't list them
+    Posts {
+        /// Read the feed of this username instead of the one saved by `init`
+        #[arg(long, value_parser = non_empty)]
+        username: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Open the config in $EDITOR, keeping the edit only if it is still valid
+    Edit,
+    /// List the saved accounts and their author IDs, marking the default
+    Profiles,
+    /// Forget a profile saved by `init --profile`
+    Remove {
+        profile: String,
+        /// Don't ask before removing it
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Delete the config file, forgetting every saved token
+    Reset {
+        /// Don't ask before deleting it
+        #[arg(long, short)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SeriesAction {
+    /// List the published parts of a series in order
+    List { name: String },
+    /// Print a markdown navigation block linking every published part
+    Link { name: String },
+}
+
+#[derive(clap::Args)]
+struct PublishArgs {
+    /// Markdown file, a directory to publish every markdown file in it, or
+    /// an http(s) URL to fetch one from. A fetched post's canonical URL is
+    /// the URL it came from unless its front matter sets one.
+    #[arg(required_unless_present_any = ["clipboard", "readme"])]
+    file: Option<PathBuf>,
+    /// Publish markdown copied to the clipboard instead of a file. Front
+    /// matter is optional; --title and friends fill in what's missing.
+    #[arg(long, conflicts_with_all = ["file", "watch"])]
+    clipboard: bool,
+    /// Publish ./README.md, taking the title from its top heading and
+    /// dropping the badges around it
+    #[arg(long, conflicts_with_all = ["file", "clipboard"])]
+    readme: bool,
+    /// Don't make the URL a post was fetched from its canonical URL
+    #[arg(long)]
+    no_auto_canonical: bool,
+    /// Publish as another author instead of the one saved by `init`.
+    /// The token must have permission to publish on that author's behalf.
+    #[arg(long, value_parser = non_empty)]
+    author_id: Option<String>,
+    /// Publish the file once to each of these profiles saved by `init
+    /// --profile`, with `default` meaning the main account. Copies after the
+    /// first successful one get it as their canonical URL, unless the front
+    /// matter sets one.
+    #[arg(long, value_delimiter = ',', value_parser = non_empty, conflicts_with_all = ["author_id", "watch", "split_on", "dry_run"])]
+    profiles: Vec<String>,
+    /// When publishing a directory, skip files that succeeded in its last run
+    #[arg(long)]
+    resume: bool,
+    /// When publishing a directory, only include files with `publish: true`
+    /// in their front matter instead of everything without `publish: false`
+    #[arg(long)]
+    require_publish_flag: bool,
+    /// When publishing a directory, only include files modified this
+    /// recently, e.g. `24h` or `3days`
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    modified_within: Option<Duration>,
+    /// Order to publish a directory's files in [default: name]
+    #[arg(long, value_enum)]
+    sort: Option<batch::Sort>,
+    /// When publishing a directory, give up on a file after this many seconds
+    /// and move on to the next, marking it timed out
+    #[arg(long, value_name = "SECONDS")]
+    timeout_per_file: Option<u64>,
+    /// Print the request that would be sent instead of publishing
+    #[arg(long)]
+    dry_run: bool,
+    /// With --dry-run, still check the saved token against Medium
+    #[arg(long, requires = "dry_run")]
+    validate_remote: bool,
+    /// With --dry-run, also show each metadata field's value and whether a
+    /// flag, the front matter or a default set it
+    #[arg(long, requires = "dry_run")]
+    show_sources: bool,
+    /// With --dry-run, show only the first N characters of the content,
+    /// followed by an ellipsis. Publishing always sends all of it.
+    #[arg(long, value_name = "N", requires = "dry_run", conflicts_with = "print_curl")]
+    preview_chars: Option<usize>,
+    /// Show what changed since the file was last published and ask before continuing
+    #[arg(long)]
+    diff: bool,
+    /// Publish even when the body below the front matter is empty
+    #[arg(long)]
+    allow_empty: bool,
+    /// Treat validation warnings as errors
+    #[arg(long)]
+    strict: bool,
+    /// Check everything before failing and list every problem found, such
+    /// as an empty body, a long title under --strict or invalid tags,
+    /// instead of stopping at the first
+    #[arg(long)]
+    collect_errors: bool,
+    /// Warn before publishing non-draft content that still contains markers
+    #[arg(long)]
+    warn_markers: bool,
+    /// Markers looked for by --warn-markers and --strict
+    #[arg(long, value_delimiter = ',', default_value = "TODO,FIXME,XXX,DRAFT")]
+    markers: Vec<String>,
+    /// Warn about `$...$` and other TeX math, which Medium shows as plain
+    /// text; with --strict, refuse to publish it
+    #[arg(long)]
+    warn_math: bool,
+    /// Warn about markdown that won't render as intended, such as unclosed
+    /// code fences or broken links; with --strict, refuse to publish it
+    #[arg(long)]
+    render_check: bool,
+    /// Warn about images loaded over plain http://, which Medium may block
+    /// or downgrade; with --strict, refuse to publish them
+    #[arg(long)]
+    warn_external_images: bool,
+    /// Publish to this publication instead of your profile; you'll be asked
+    /// to confirm unless --yes is given
+    #[arg(long, value_parser = non_empty)]
+    publication_id: Option<String>,
+    /// Don't ask before publishing to a publication, using --auto-tags or
+    /// repeating a title found by --retry-idempotency-check
+    #[arg(long, short)]
+    yes: bool,
+    /// Before publishing, look for a post with the same title in your public
+    /// RSS feed and ask before publishing it again. The feed only has your
+    /// latest public posts and lags behind Medium, so drafts, unlisted posts,
+    /// older posts and ones published moments ago aren't found.
+    #[arg(long)]
+    retry_idempotency_check: bool,
+    /// Tag posts that have no tags with their `#hashtags` and most repeated
+    /// words, up to five. You're asked to confirm them unless --yes is given
+    /// or the input isn't a terminal.
+    #[arg(long)]
+    auto_tags: bool,
+    /// Publish each part of the file between these markers, e.g.
+    /// `<!-- split -->`, as a separate post titled by its first heading
+    #[arg(long, value_name = "MARKER", value_parser = non_empty, conflicts_with_all = ["watch", "diff"])]
+    split_on: Option<String>,
+    /// With --split-on, which parts the front matter applies to
+    #[arg(long, value_enum, default_value_t = SplitMetadata::All, requires = "split_on")]
+    split_metadata: SplitMetadata,
+    /// Republish the file as a draft every time it is saved. Medium cannot
+    /// update posts, so each save creates a new draft.
+    #[arg(long, conflicts_with_all = ["dry_run", "resume", "diff"])]
+    watch: bool,
+    /// Publish even if an identical earlier request may already have created
+    /// the post. Medium has no idempotency keys, so this can duplicate it.
+    #[arg(long)]
+    force: bool,
+    /// Publish even when local images fail to upload, with a note in place
+    /// of each one, instead of stopping after listing every failure
+    #[arg(long)]
+    skip_failed_images: bool,
+    /// Try things out safely: publish as a draft, without uploading images,
+    /// adding the canonical footer or changing the content in any way
+    #[arg(long)]
+    safe_mode: bool,
+    /// Publish again a post first published on Medium at this URL, with the
+    /// canonical URL on your own site and a note that it moved there.
+    /// Medium's API can't change or delete posts, so this makes a new post
+    /// and the old one stays up until you delete or unlist it on Medium.
+    #[arg(long, value_name = "MEDIUM_URL", conflicts_with = "safe_mode")]
+    republish_as_canonical: Option<Url>,
+    /// When publishing a directory, add this tag to every post in it, unless
+    /// the post already has it or is at Medium's limit of five. Repeatable.
+    #[arg(long, value_name = "TAG", value_parser = non_empty, conflicts_with_all = ["watch", "clipboard"])]
+    add_tag_all: Vec<String>,
+    /// Check that the canonical URL is reachable before publishing, warning
+    /// if it isn't. This sends a request to the canonical URL's host.
+    #[arg(long)]
+    check_canonical: bool,
+    /// Print the request as an equivalent `curl` command before sending it,
+    /// or instead of sending it with --dry-run. The token is left out.
+    #[arg(long)]
+    print_curl: bool,
+    /// Include the real token in --print-curl output. Anyone who sees it can
+    /// publish as you.
+    #[arg(long, requires = "print_curl")]
+    insecure_print_token: bool,
+    /// Print this instead of the usual message once a file is published,
+    /// filling in {url}, {title}, {status} and {id}, e.g. "{title}: {url}".
+    /// --json takes precedence.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_output_template)]
+    output_template: Option<String>,
+    /// Time reading, transforming, uploading and publishing each file, and
+    /// print the min/max/average/total of each to stderr when done
+    #[arg(long)]
+    metrics: bool,
+    /// Shorten titles over Medium's 100 character limit instead of warning
+    #[arg(long)]
+    truncate_title: bool,
+    /// Split tags like `"rust, cli"` at their commas instead of rejecting them
+    #[arg(long)]
+    split_tag_commas: bool,
+    /// File listing the allowed tags, one per line; other tags are reported
+    #[arg(long, value_name = "PATH", value_parser = validate::read_tag_vocabulary)]
+    tags_file: Option<validate::TagVocabulary>,
+    /// POST each file's outcome as JSON to this URL, e.g. a Slack or Discord
+    /// incoming webhook, instead of the one saved in the config
+    #[arg(long, value_name = "URL")]
+    webhook: Option<Url>,
+    /// JSON schema each file's front matter must satisfy, instead of the
+    /// one saved in the config. `$ref`s to other files are resolved
+    /// relative to it.
+    #[arg(long, value_name = "PATH")]
+    schema: Option<PathBuf>,
+    #[command(flatten)]
+    post: PostOptions,
+}
+
+/// How a post's metadata and content are put together
+#[derive(clap::Args)]
+struct PostOptions {
+    #[command(flatten)]
+    metadata: MetadataOptions,
+    #[command(flatten)]
+    transform: TransformOptions,
+}
+
+/// Metadata flags, which take precedence over front matter
+#[derive(clap::Args, Default)]
+struct MetadataOptions {
+    /// Post title
+    #[arg(long)]
+    title: Option<String>,
+    /// Comma-separated tags
+    #[arg(long, value_delimiter = ',')]
+    tags: Option<Vec<String>>,
+    /// Publish status
+    #[arg(long, value_enum)]
+    status: Option<PublishStatus>,
+    /// Publish as a response to this post, given by its ID: the hex string
+    /// at the end of its URL, e.g. `1a2b3c4d5e6f` in
+    /// `medium.com/@author/some-title-1a2b3c4d5e6f`
+    #[arg(long, value_name = "POST_ID", value_parser = non_empty)]
+    in_response_to: Option<String>,
+    /// How to read content piped in with `-` as the file
+    #[arg(long, value_enum, default_value_t = StdinFormat::FrontMatter)]
+    stdin_format: StdinFormat,
+    /// Character encoding of the file or stdin, such as `windows-1252` or
+    /// `latin1`, for older files that aren't UTF-8
+    #[arg(long, value_name = "NAME", value_parser = parse_encoding)]
+    encoding: Option<&'static Encoding>,
+    /// Text prepared by `publish --clipboard` or `--readme`, used instead of
+    /// reading the file; front matter in it is optional
+    #[arg(skip)]
+    supplied_text: Option<String>,
+    /// Where `supplied_text` was fetched from, the canonical URL unless the
+    /// front matter gives one
+    #[arg(skip)]
+    source_url: Option<Url>,
+    /// Front matter inherited from the `_defaults` file of a published directory
+    #[arg(skip)]
+    defaults: Option<batch::Defaults>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum SplitMetadata {
+    /// Every part gets the file's tags, status and other metadata
+    All,
+    /// Only the first part does; the rest are published with defaults
+    First,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+enum StdinFormat {
+    /// Front matter followed by the body, like a file
+    #[default]
+    FrontMatter,
+    /// Only the body; metadata comes from flags
+    Body,
+}
+
+impl PublishArgs {
+    /// The file to publish; with --clipboard there is none, which is labelled like stdin
+    fn file(&self) -> &Path {
+        self.file.as_deref().unwrap_or_else(|| Path::new("-"))
+    }
+
+    /// Where to report each outcome: --webhook, or the config's `webhook`
+    fn webhook<'a>(&'a self, config: &'a ApiConfig) -> Option<&'a str> {
+        self.webhook.as_ref().map(Url::as_str).or(config.webhook.as_deref())
+    }
+
+    /// The schema front matter is checked against: --schema, or the config's `schema`
+    fn schema<'a>(&'a self, config: &'a ApiConfig) -> Option<&'a Path> {
+        self.schema.as_deref().or(config.schema.as_deref())
+    }
+}
+
+fn non_empty(value: &str) -> Result<String, String> {
+    if value.trim().is_empty() {
+        Err("must not be empty".to_string())
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// An encoding by any of its WHATWG labels, like `latin1` or `windows-1252`
+fn parse_encoding(value: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(value.trim().as_bytes()).ok_or_else(|| format!("unknown encoding {:?}", value))
+}
+
+/// Placeholders --output-template fills in
+const OUTPUT_PLACEHOLDERS: [&str; 4] = ["url", "title", "status", "id"];
+
+fn output_placeholder() -> Regex {
+    Regex::new(r"\{([^{}]*)\}").unwrap()
+}
+
+fn parse_output_template(value: &str) -> Result<String, String> {
+    for placeholder in output_placeholder().captures_iter(value) {
+        if !OUTPUT_PLACEHOLDERS.contains(&&placeholder[1]) {
+            return Err(format!(
+                "unknown placeholder {}; use {{url}}, {{title}}, {{status}} or {{id}}",
+                &placeholder[0]
+            ));
+        }
+    }
+    Ok(value.to_string())
+}
+
+/// `template` with each placeholder replaced by that field of the published post
+fn render_output_template(template: &str, entry: &history::HistoryEntry) -> String {
+    output_placeholder()
+        .replace_all(template, |placeholder: &regex::Captures| match &placeholder[1] {
+            "url" => entry.url.clone(),
+            "title" => entry.title.clone(),
+            "status" => entry.status.to_string(),
+            _ => entry.id.clone().unwrap_or_default(),
+        })
+        .into_owned()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MediumUser {
     id: String,
+    #[serde(default)]
+    username: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,6 +597,17 @@ struct MediumUserResponse {
     data: MediumUser,
 }
 
+#[derive(Debug, Deserialize)]
+struct Publication {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublicationsResponse {
+    data: Vec<Publication>,
+}
+
 #[derive(Debug, Serialize, Deserialize, ValueEnum, Clone)]
 enum PublishStatus {
     #[serde(rename = "public")]
@@ -55,24 +628,173 @@ impl fmt::Display for PublishStatus {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct ApiConfig {
     token: String,
     id: String,
+    /// User-Agent sent instead of `markmedium/<version>`, saved by `init --user-agent`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user_agent: Option<String>,
+    /// When `init` saved the token, RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    /// Medium username from `/v1/me`, used to find the public feed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    /// URL notified after each publish when --webhook isn't given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    webhook: Option<String>,
+    /// JSON schema for front matter when --schema isn't given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema: Option<PathBuf>,
+    /// Other accounts saved by `init --profile`, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// Credentials of an account besides the default one
+#[derive(Serialize, Deserialize, Clone)]
+struct Profile {
+    token: String,
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+}
+
+impl ApiConfig {
+    /// The config with the credentials of the profile `name` swapped in
+    fn profile(&self, name: &str) -> Result<ApiConfig> {
+        if name == DEFAULT_PROFILE {
+            return Ok(self.clone());
+        }
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            anyhow!("No profile named {}; save one with `markmedium init <token> --profile {}`", name, name)
+        })?;
+        Ok(ApiConfig {
+            token: profile.token.clone(),
+            id: profile.id.clone(),
+            created_at: profile.created_at.clone(),
+            username: profile.username.clone(),
+            profiles: BTreeMap::new(),
+            ..self.clone()
+        })
+    }
+
+    fn token_age(&self) -> Option<Duration> {
+        let created_at = humantime::parse_rfc3339(self.created_at.as_deref()?).ok()?;
+        SystemTime::now().duration_since(created_at).ok()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct PublishMetadata {
-    title: String,
     #[serde(default)]
+    title: String,
+    /// Always taken from the body below the front matter
+    #[serde(default, deserialize_with = "reject_content")]
     content: String,
     #[serde(rename(serialize = "contentFormat"), default = "default_content_format")]
     content_format: String,
+    #[serde(default, deserialize_with = "tag_list")]
     tags: Option<Vec<String>>,
     #[serde(rename(serialize = "canonicalUrl"))]
     canonical_url: Option<String>,
     #[serde(rename(serialize = "publishStatus"))]
     status: Option<PublishStatus>,
+    /// Whether directory publishing should include this file
+    #[serde(skip_serializing)]
+    publish: Option<bool>,
+    /// Names local artifacts and history records; defaults to the slugified title
+    #[serde(skip_serializing)]
+    slug: Option<String>,
+    /// Membership in a multi-part series, recorded in the history
+    #[serde(skip_serializing)]
+    series: Option<Series>,
+    /// Image placed above the content so Medium uses it as the cover
+    #[serde(skip_serializing)]
+    cover: Option<String>,
+    /// Whether Medium emails followers about a public post
+    #[serde(rename(serialize = "notifyFollowers"), skip_serializing_if = "Option::is_none")]
+    notify_followers: Option<bool>,
+    /// When the post was meant to go out, as an RFC 3339 time or a date.
+    /// The API can't schedule posts, so a future time only makes this a draft.
+    #[serde(skip_serializing)]
+    scheduled_for: Option<String>,
+    /// ID of the post this one responds to, from the end of its URL
+    #[serde(rename(serialize = "inResponseToPostId"), skip_serializing_if = "Option::is_none")]
+    in_response_to: Option<String>,
+    /// Shown to readers in a note above the content, since Medium has no
+    /// content warnings of its own
+    #[serde(skip_serializing)]
+    content_warning: Option<String>,
+    /// Old URLs of the post, recorded in the history for redirecting them
+    #[serde(skip_serializing)]
+    aliases: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Series {
+    name: String,
+    index: u32,
+    total: Option<u32>,
+}
+
+impl Default for PublishMetadata {
+    fn default() -> Self {
+        PublishMetadata {
+            title: String::new(),
+            content: String::new(),
+            content_format: default_content_format(),
+            tags: None,
+            canonical_url: None,
+            status: None,
+            publish: None,
+            slug: None,
+            series: None,
+            cover: None,
+            notify_followers: None,
+            scheduled_for: None,
+            in_response_to: None,
+            content_warning: None,
+            aliases: None,
+        }
+    }
+}
+
+impl PublishMetadata {
+    fn slug(&self) -> String {
+        match &self.slug {
+            Some(slug) => slug.to_owned(),
+            None => match transform::slugify(&self.title) {
+                slug if slug.is_empty() => "post".to_string(),
+                slug => slug,
+            },
+        }
+    }
+}
+
+fn reject_content<'de, D: Deserializer<'de>>(_: D) -> Result<String, D::Error> {
+    Err(de::Error::custom(
+        "`content` is reserved; the post body is taken from below the front matter",
+    ))
+}
+
+/// Tags as a YAML list, or as one comma-separated string like
+/// `"rust, cli"`, which is split, trimmed and deduplicated. An empty
+/// string is the same as no tags.
+fn tag_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    let invalid = || de::Error::custom("`tags` must be a list or a comma-separated string");
+    Ok(match value {
+        Some(serde_json::Value::String(text)) => {
+            Some(validate::split_tag_commas(&[text])).filter(|tags| !tags.is_empty())
+        }
+        Some(list @ serde_json::Value::Array(_)) => Some(serde_json::from_value(list).map_err(|_| invalid())?),
+        Some(serde_json::Value::Null) | None => None,
+        Some(_) => return Err(invalid()),
+    })
 }
 
 fn default_content_format() -> String {
@@ -81,7 +803,17 @@ fn default_content_format() -> String {
 
 #[derive(Deserialize)]
 struct PublishedPost {
+    #[serde(default)]
+    id: Option<String>,
     url: String,
+    #[serde(rename = "publishStatus", default, deserialize_with = "known_status")]
+    publish_status: Option<PublishStatus>,
+}
+
+/// A missing or unrecognised status must not make a published post look like a failure
+fn known_status<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PublishStatus>, D::Error> {
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.and_then(|value| serde_json::from_value(value).ok()))
 }
 
 #[derive(Deserialize)]
@@ -92,6 +824,9 @@ struct PublishResponse {
 #[derive(Debug, Serialize, Deserialize)]
 struct ErrorBody {
     message: String,
+    /// Medium's own error number, which tells apart failures sharing a status
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -106,43 +841,507 @@ enum ResponseType<T> {
     Err(ErrorResponse),
 }
 
-async fn init(token: &String) -> anyhow::Result<PathBuf> {
-    let file_path = home_dir().unwrap().join(FILE_NAME);
-    let response: reqwest::Response = reqwest::Client::new()
-        .get("https://api.medium.com/v1/me")
-        .bearer_auth(token)
-        .send()
-        .await?;
+#[derive(Debug)]
+enum ApiError {
+    /// The token was rejected (401 or 403)
+    Unauthorized,
+    Api {
+        status: reqwest::StatusCode,
+        message: String,
+        code: Option<i64>,
+    },
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Unauthorized => write!(
+                f,
+                "Your token appears invalid or expired. Generate a new one at {} and run `markmedium init <token>` to update it.",
+                TOKEN_SETTINGS_URL
+            ),
+            ApiError::Api {
+                status,
+                message,
+                code: Some(code),
+            } => write!(f, "API error ({}, code {}): {}", status.as_u16(), code, message),
+            ApiError::Api { status, message, .. } => write!(f, "API error ({}): {}", status.as_u16(), message),
+        }
+    }
+}
 
-    let response: ResponseType<MediumUserResponse> = response.json().await?;
+impl std::error::Error for ApiError {}
 
-    match response {
-        ResponseType::Ok(user_response) => {
-            let user_data = user_response.data;
+/// A failure already reported on stdout, as `--json` does, so `main` only
+/// needs to exit non-zero
+#[derive(Debug)]
+struct AlreadyReported;
 
-            let config = ApiConfig {
-                token: token.to_string(),
-                id: user_data.id,
-            };
+impl fmt::Display for AlreadyReported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failure already reported")
+    }
+}
 
-            let json_config = serde_json::to_string(&config)?;
+impl std::error::Error for AlreadyReported {}
+
+/// Decode a Medium response, mapping error bodies onto `ApiError`
+async fn read_response<T: de::DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let status = response.status();
+    #[cfg(feature = "tracing")]
+    tracing::debug!(%status, url = %response.url(), "received response");
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(ApiError::Unauthorized.into());
+    }
 
-            std::fs::write(file_path.clone(), json_config)?;
-            Ok(file_path)
+    let body = response.bytes().await?;
+    let response: ResponseType<T> = match serde_json::from_slice(&body) {
+        Ok(response) => response,
+        // Proxies and outages answer with HTML; the status is what matters then
+        Err(_) if !status.is_success() => {
+            return Err(ApiError::Api {
+                status,
+                message: status.canonical_reason().unwrap_or("request failed").to_string(),
+                code: None,
+            }
+            .into())
         }
+        Err(err) => return Err(err.into()),
+    };
+
+    match response {
+        ResponseType::Ok(data) => Ok(data),
         ResponseType::Err(error_response) => {
-            Err(anyhow!(error_response.errors[0].message.to_owned()))
+            let error = error_response.errors.into_iter().next();
+            Err(ApiError::Api {
+                status,
+                code: error.as_ref().and_then(|error| error.code),
+                message: error
+                    .map(|error| error.message)
+                    .unwrap_or_else(|| "request failed".to_string()),
+            }
+            .into())
+        }
+    }
+}
+
+/// Set from --config before any command runs
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// The config file: --config, or ~/.markmedium unless only ~/.markmedium.toml exists
+fn config_path() -> PathBuf {
+    if let Some(path) = CONFIG_PATH.get() {
+        return path.clone();
+    }
+    let home = home_dir().unwrap();
+    let (json, toml) = (home.join(FILE_NAME), home.join(TOML_FILE_NAME));
+    if !json.exists() && toml.exists() {
+        toml
+    } else {
+        json
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
+}
+
+fn parse_config(text: &str, path: &Path) -> Result<ApiConfig> {
+    if is_toml(path) {
+        Ok(toml::from_str(text)?)
+    } else {
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+fn write_config(config: &ApiConfig) -> Result<PathBuf> {
+    let file_path = config_path();
+    let text = if is_toml(&file_path) {
+        toml::to_string(config)?
+    } else {
+        serde_json::to_string(config)?
+    };
+    std::fs::write(file_path.clone(), text)?;
+    Ok(file_path)
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+async fn fetch_user(client: &Client, token: &str) -> Result<MediumUser> {
+    let response: reqwest::Response = client
+        .send(client.get("https://api.medium.com/v1/me").bearer_auth(token))
+        .await?;
+
+    let user_response: MediumUserResponse = read_response(response).await?;
+    Ok(user_response.data)
+}
+
+async fn init(
+    token: &String,
+    id: Option<String>,
+    profile: Option<&str>,
+    client_options: &ClientOptions,
+) -> anyhow::Result<PathBuf> {
+    // Save the User-Agent override given here, or keep one from a previous config
+    let previous = read_config().ok();
+    let user_agent = client_options
+        .user_agent
+        .clone()
+        .or_else(|| previous.as_ref().and_then(|config| config.user_agent.clone()));
+
+    // A known ID skips the `/v1/me` lookup entirely
+    let (id, username) = match id {
+        Some(id) => (id, None),
+        None => {
+            let user = fetch_user(&client_options.build(user_agent.as_deref())?, token).await?;
+            (user.id, user.username)
         }
+    };
+
+    let created_at = Some(humantime::format_rfc3339_seconds(SystemTime::now()).to_string());
+
+    if let Some(name) = profile.filter(|name| *name != DEFAULT_PROFILE) {
+        let mut config = previous
+            .ok_or_else(|| anyhow!("Set up the default account with `markmedium init <token>` before adding profiles"))?;
+        let profile = Profile {
+            token: token.to_string(),
+            id,
+            created_at,
+            username,
+        };
+        config.profiles.insert(name.to_string(), profile);
+        return write_config(&config);
     }
+
+    let (webhook, schema, profiles) = match previous {
+        Some(config) => (config.webhook, config.schema, config.profiles),
+        None => (None, None, BTreeMap::new()),
+    };
+    write_config(&ApiConfig {
+        token: token.to_string(),
+        id,
+        user_agent,
+        created_at,
+        username,
+        webhook,
+        schema,
+        profiles,
+    })
 }
 
 fn read_config() -> Result<ApiConfig> {
-    let file_path = home_dir().unwrap().join(FILE_NAME);
-    let text: String = std::fs::read_to_string(file_path)?;
-    let config: ApiConfig = serde_json::from_str(&text)?;
+    let file_path = config_path();
+    let text: String = std::fs::read_to_string(&file_path)?;
+    let config = parse_config(&text, &file_path)?;
+    tee::redact(&config.token);
+    for profile in config.profiles.values() {
+        tee::redact(&profile.token);
+    }
     Ok(config)
 }
 
+/// The token for `init`: the contents of `token_file`, trimmed, otherwise
+/// `token`, otherwise MEDIUM_TOKEN
+fn init_token(token: Option<&str>, token_file: Option<&Path>) -> Result<String> {
+    let token = match (token_file, token) {
+        (Some(path), _) => std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("Could not read token file {}: {}", path.display(), err))?,
+        (None, Some(token)) => token.to_string(),
+        (None, None) => std::env::var(TOKEN_ENV)
+            .map_err(|_| anyhow!("Pass a token, --token-file or set {}", TOKEN_ENV))?,
+    };
+    match token.trim() {
+        "" => Err(anyhow!("The token is empty")),
+        token => Ok(token.to_string()),
+    }
+}
+
+/// Credentials from MEDIUM_TOKEN and MEDIUM_AUTHOR_ID when both are set,
+/// otherwise the saved config. Commands that only need the credentials use
+/// this; ones that edit the config read the file itself.
+fn load_config() -> Result<ApiConfig> {
+    let variable = |name| std::env::var(name).ok().filter(|value: &String| !value.trim().is_empty());
+    if let (Some(token), Some(id)) = (variable(TOKEN_ENV), variable(AUTHOR_ID_ENV)) {
+        tee::redact(&token);
+        return Ok(ApiConfig {
+            token,
+            id,
+            user_agent: None,
+            created_at: None,
+            username: None,
+            webhook: None,
+            schema: None,
+            profiles: BTreeMap::new(),
+        });
+    }
+    read_config()
+}
+
+async fn status(client_options: &ClientOptions) -> Result<()> {
+    let config = load_config()?;
+    let client = client_options.build(config.user_agent.as_deref())?;
+
+    match config.token_age() {
+        Some(age) => {
+            let days = age.as_secs() / (24 * 60 * 60);
+            outln!("Token saved {} days ago", days);
+            if age > TOKEN_MAX_AGE {
+                errln!(
+                    "Warning: your token is over a year old; if it stops working, generate a new one at {}",
+                    TOKEN_SETTINGS_URL
+                );
+            }
+        }
+        None => outln!("Token age unknown; run `markmedium init` again to record it"),
+    }
+
+    let user = fetch_user(&client, &config.token).await?;
+    match user.username {
+        Some(username) => outln!("Token is valid for @{} ({})", username, user.id),
+        None => outln!("Token is valid for author {}", user.id),
+    }
+    Ok(())
+}
+
+/// A post listed in a public RSS feed
+struct FeedPost {
+    title: String,
+    url: String,
+    published: Option<String>,
+}
+
+async fn posts(username: Option<&str>, client_options: &ClientOptions) -> Result<Vec<FeedPost>> {
+    let config = read_config().ok();
+    let username = match username {
+        Some(username) => username.to_string(),
+        None => config
+            .as_ref()
+            .and_then(|config| config.username.clone())
+            .ok_or_else(|| anyhow!("No username saved; run `markmedium init <token>` again or pass --username"))?,
+    };
+    let client = client_options.build(config.as_ref().and_then(|config| config.user_agent.as_deref()))?;
+    feed_posts(&client, &username).await
+}
+
+/// The latest public posts of `username`, from their RSS feed
+async fn feed_posts(client: &Client, username: &str) -> Result<Vec<FeedPost>> {
+    let url = format!("https://medium.com/feed/@{}", username.trim_start_matches('@'));
+    let response = client.send(client.get(&url)).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Could not fetch {}: {}", url, response.status()));
+    }
+    let channel = rss::Channel::read_from(&response.bytes().await?[..])
+        .map_err(|err| anyhow!("Could not read the feed at {}: {}", url, err))?;
+
+    Ok(channel
+        .items()
+        .iter()
+        .filter_map(|item| {
+            Some(FeedPost {
+                title: item.title()?.to_string(),
+                url: item.link()?.to_string(),
+                published: item.pub_date().map(str::to_string),
+            })
+        })
+        .collect())
+}
+
+/// Refuse to republish without a canonical URL off Medium for the post to
+/// move to, and explain what's left to do by hand since the API can't
+fn check_republish(mdfile: &Path, metadata: &PublishMetadata, moved_from: &Url) -> Result<()> {
+    let Some(canonical_url) = &metadata.canonical_url else {
+        return Err(anyhow!(
+            "{}: --republish-as-canonical needs the post's URL on your own site; set canonical_url in the front matter",
+            mdfile.display()
+        ));
+    };
+    let on_medium = Url::parse(canonical_url)?
+        .host_str()
+        .is_some_and(|host| host == "medium.com" || host.ends_with(".medium.com"));
+    if on_medium {
+        return Err(anyhow!(
+            "{}: canonical URL {} is on Medium; point it at your own site",
+            mdfile.display(),
+            canonical_url
+        ));
+    }
+    errln!(
+        "Warning: Medium's API can't change or delete {}, so this publishes a new post with the canonical URL {}. \
+         Once it's up, delete or unlist the old post on Medium so readers and search engines find one copy.",
+        moved_from, canonical_url
+    );
+    Ok(())
+}
+
+/// Ask before publishing `title` again when the public feed already has a
+/// post by that name, so a re-run after a failure doesn't make a duplicate.
+/// An unreadable feed only warns, since it can't show there is one.
+async fn check_duplicate_title(client: &Client, config: &ApiConfig, title: &str, yes: bool) -> Result<()> {
+    let Some(username) = &config.username else {
+        return Err(anyhow!(
+            "--retry-idempotency-check needs your username to find your feed; run `markmedium init` again to save it"
+        ));
+    };
+    let posts = match feed_posts(client, username).await {
+        Ok(posts) => posts,
+        Err(err) => {
+            errln!("Warning: could not check for an existing post: {}", err);
+            return Ok(());
+        }
+    };
+    let Some(existing) = posts.iter().find(|post| post.title.trim().eq_ignore_ascii_case(title.trim())) else {
+        return Ok(());
+    };
+    errln!("Warning: \"{}\" is already published at {}", existing.title, existing.url);
+    if yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!("Not publishing a duplicate; pass --yes to publish it anyway"));
+    }
+    if !confirm("Publish it again?")? {
+        return Err(anyhow!("Publishing cancelled"));
+    }
+    Ok(())
+}
+
+/// Pass/fail probe: the config parses, has a token, and `/v1/me` accepts it
+async fn check(client_options: &ClientOptions) -> Result<MediumUser> {
+    let config = load_config().map_err(|err| anyhow!("Could not read config: {}", err))?;
+    if config.token.trim().is_empty() {
+        return Err(anyhow!("Config has no token"));
+    }
+    let client = client_options.build(config.user_agent.as_deref())?;
+    fetch_user(&client, &config.token).await
+}
+
+/// Each saved account as (name, author ID, username), the default first
+fn list_profiles(config: &ApiConfig) -> Vec<(&str, &str, Option<&str>)> {
+    std::iter::once((DEFAULT_PROFILE, config.id.as_str(), config.username.as_deref()))
+        .chain(config.profiles.iter().map(|(name, profile)| {
+            (name.as_str(), profile.id.as_str(), profile.username.as_deref())
+        }))
+        .collect()
+}
+
+fn print_profiles(config: &ApiConfig, json: bool) -> Result<()> {
+    let profiles = list_profiles(config);
+    if json {
+        let profiles: Vec<_> = profiles
+            .iter()
+            .map(|(name, id, username)| {
+                serde_json::json!({
+                    "name": name,
+                    "author_id": id,
+                    "username": username,
+                    "default": *name == DEFAULT_PROFILE,
+                })
+            })
+            .collect();
+        outln!("{}", serde_json::to_string_pretty(&profiles)?);
+        return Ok(());
+    }
+
+    let width = profiles.iter().map(|(name, ..)| name.chars().count()).max().unwrap_or(0);
+    for (name, id, username) in profiles {
+        let marker = if name == DEFAULT_PROFILE { "*" } else { " " };
+        match username {
+            Some(username) => outln!("{} {:width$}  {}  @{}", marker, name, id, username),
+            None => outln!("{} {:width$}  {}", marker, name, id),
+        }
+    }
+    Ok(())
+}
+
+/// Ask before a destructive change unless `yes`, refusing when nobody can answer
+fn confirm_destructive(question: &str, yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!("Pass --yes to confirm when not run interactively"));
+    }
+    confirm(question)
+}
+
+/// Forget the profile `name`, returning the config without it
+fn remove_profile(name: &str, yes: bool) -> Result<Option<ApiConfig>> {
+    let mut config = read_config()?;
+    if name == DEFAULT_PROFILE {
+        return Err(anyhow!("The default account can't be removed; use `markmedium config reset` instead"));
+    }
+    if !config.profiles.contains_key(name) {
+        return Err(anyhow!("No profile named {}; `markmedium config profiles` lists them", name));
+    }
+    if !confirm_destructive(&format!("Remove the profile {}?", name), yes)? {
+        return Ok(None);
+    }
+    config.profiles.remove(name);
+    write_config(&config)?;
+    Ok(Some(config))
+}
+
+/// Delete the config file, returning where it was if it was deleted
+fn reset_config(yes: bool) -> Result<Option<PathBuf>> {
+    let file_path = config_path();
+    if !file_path.exists() {
+        return Err(anyhow!("There is no config at {} to reset", file_path.display()));
+    }
+    let question = format!("Delete {}, forgetting every saved token?", file_path.display());
+    if !confirm_destructive(&question, yes)? {
+        return Ok(None);
+    }
+    std::fs::remove_file(&file_path)?;
+    Ok(Some(file_path))
+}
+
+/// Edit a copy of the config so an invalid save never replaces the original
+fn edit_config() -> Result<PathBuf> {
+    let file_path = config_path();
+    let original = std::fs::read_to_string(&file_path)
+        .map_err(|err| anyhow!("Could not read {}: {}; run `markmedium init` first", file_path.display(), err))?;
+
+    // Same extension as the config, so editors highlight it and it's parsed alike
+    let extension = if is_toml(&file_path) { "toml" } else { "json" };
+    let draft_path = std::env::temp_dir().join(format!("markmedium-config-{}.{}", std::process::id(), extension));
+    std::fs::write(&draft_path, &original)?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or_else(|| anyhow!("$EDITOR is empty"))?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&draft_path)
+        .status()?;
+
+    if !status.success() {
+        std::fs::remove_file(&draft_path)?;
+        return Err(anyhow!("{} exited with {}; config left unchanged", program, status));
+    }
+
+    let edited = std::fs::read_to_string(&draft_path)?;
+    if let Err(err) = parse_config(&edited, &draft_path) {
+        return Err(anyhow!(
+            "Edited config is invalid ({}); config left unchanged, your edit is kept at {}",
+            err,
+            draft_path.display()
+        ));
+    }
+    std::fs::write(&file_path, edited)?;
+    std::fs::remove_file(&draft_path)?;
+    Ok(file_path)
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no
+fn confirm(question: &str) -> Result<bool> {
+    out!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
 fn base_url(mut url: Url) -> Result<Url> {
     match url.path_segments_mut() {
         Ok(mut path) => {
@@ -158,71 +1357,1734 @@ fn base_url(mut url: Url) -> Result<Url> {
     Ok(url)
 }
 
-fn get_canonical_reference(canonical_url: String) -> Result<String, anyhow::Error> {
+/// The "Originally published at" footer, after `separator` or a horizontal rule
+fn get_canonical_reference(
+    canonical_url: String,
+    content_format: &str,
+    separator: Option<&str>,
+) -> Result<String, anyhow::Error> {
     let url = Url::parse(&canonical_url)?;
     let base = base_url(url.clone())?;
+    let base = base.as_str().trim_end_matches('/');
+
+    // Match the body's format so HTML posts don't end in raw markdown
+    if content_format == "html" {
+        return Ok(format!(
+            "{}<p><em>Originally published at <a href=\"{}\">{}</a>.</em></p>",
+            separator.unwrap_or("\n<hr>"),
+            escape_html(url.as_str()),
+            escape_html(base)
+        ));
+    }
+
     Ok(
         format!(
-            "\n\n---\n\n*Originally published at [{}]({}).*",
-            base.as_str().trim_end_matches('/'),
+            "{}*Originally published at [{}]({}).*",
+            separator.unwrap_or("\n\n---\n\n"),
+            base,
             url
         )
     )
 }
 
+fn read_clipboard() -> Result<String> {
+    let text = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|err| match err {
+            arboard::Error::ContentNotAvailable => anyhow!("The clipboard is empty or doesn't hold text"),
+            err => anyhow!("Could not read the clipboard: {}", err),
+        })?;
+    if text.trim().is_empty() {
+        return Err(anyhow!("The clipboard is empty or doesn't hold text"));
+    }
+    Ok(text)
+}
 
-async fn publish(mdfile: PathBuf) -> Result<String, anyhow::Error> {
-    let config = read_config()?;
-    let input = std::fs::read_to_string(mdfile)?;
-    let document: Document<PublishMetadata> = YamlFrontMatter::parse::<PublishMetadata>(&input).unwrap();
-    let Document { mut metadata, content } = document;
-
-    metadata.content = content;
+/// Read `mdfile`, or stdin for `-`, decoding it from `encoding` when given
+/// and otherwise requiring UTF-8
+fn read_input(mdfile: &Path, encoding: Option<&'static Encoding>) -> Result<String> {
+    let bytes = if mdfile == Path::new("-") {
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input)?;
+        input
+    } else {
+        std::fs::read(mdfile)?
+    };
+    match encoding {
+        Some(encoding) => {
+            let (text, had_errors) = encoding.decode_without_bom_handling(&bytes);
+            if had_errors {
+                errln!(
+                    "Warning: {} has bytes that aren't valid {}; they were replaced with U+FFFD",
+                    mdfile.display(),
+                    encoding.name()
+                );
+            }
+            Ok(text.into_owned())
+        }
+        None => String::from_utf8(bytes).map_err(|err| {
+            anyhow!(
+                "{} is not valid UTF-8 ({}); pass its encoding with --encoding, e.g. --encoding windows-1252",
+                mdfile.display(),
+                err.utf8_error()
+            )
+        }),
+    }
+}
 
-    if let Some(ref canonical_url) = metadata.canonical_url {
-        // Add the "Originally published at XXX"
-        metadata.content += get_canonical_reference(canonical_url.to_string())?.as_str();
-    }
-        
-
-    let response: reqwest::Response = reqwest::Client::new()
-        .post(format!(
-            "https://api.medium.com/v1/users/{}/posts",
-            config.id
-        ))
-        .bearer_auth(config.token)
-        .json(&metadata)
-        .send()
-        .await?;
+/// A post split into metadata and body, before any transformation
+struct ParsedPost {
+    metadata: PublishMetadata,
+    content: String,
+    /// Byte offset in the source where `content` begins, for mapping
+    /// problems in the body back to lines of the file. markmedium has no
+    /// library API, so outside it this is only exposed as `inspect`'s
+    /// `contentOffset`.
+    content_offset: usize,
+    /// Keys set by the file's own front matter, before defaults and flags
+    front_matter_keys: Vec<String>,
+}
 
-    let response: ResponseType<PublishResponse> = response.json().await?;
-    
-    match response {
-        ResponseType::Ok(publish_response) => {
-            let publish_data = publish_response.data;
-            Ok(publish_data.url)
-        }
-        ResponseType::Err(error_response) =>  {
-            Err(anyhow!(error_response.errors[0].message.to_owned()))
+/// Where `yaml-front-matter` starts the body: after the line closing the
+/// front matter, or after every line when it's never closed
+fn content_offset(input: &str) -> usize {
+    let mut offset = 0;
+    let mut opened = false;
+    for line in input.split_inclusive('\n') {
+        offset += line.len();
+        if line.trim() == "---" {
+            if opened {
+                break;
+            }
+            opened = true;
         }
     }
+    offset
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+/// Download a post to publish from `url`, pointing its relative images at
+/// where they sit next to it, since there's no local file to upload them from
+async fn fetch_post(client: &Client, url: &Url) -> Result<String> {
+    let response = client.send(client.get(url.as_str())).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Could not fetch {}: {}", url, response.status()));
+    }
+    let text = response.text().await?;
+    let images: HashMap<String, String> = images::image_links(&text)
+        .into_iter()
+        .filter(|link| images::is_local(&link.src))
+        .filter_map(|link| Some((link.src.clone(), url.join(&link.src).ok()?.to_string())))
+        .collect();
+    Ok(images::replace_sources(&text, &images))
+}
 
-    match &args.command {
-        Some(Commands::Init { token }) => {
-            let file_path = init(token).await?;
-            println!("Saved token and author ID at {}", file_path.display());
-        }
-        Some(Commands::Publish { file }) => {
-            let url = publish(file.to_owned()).await?;
-            println!("Done! Your post has been published at {}", url);
-        }
-        None => {}
+/// Read front matter and body from `mdfile`, with flags taking precedence
+fn parse_metadata(
+    mdfile: &Path,
+    overrides: &MetadataOptions,
+    expand_includes: bool,
+) -> Result<ParsedPost, anyhow::Error> {
+    let input = match &overrides.supplied_text {
+        Some(text) => text.to_owned(),
+        None => read_input(mdfile, overrides.encoding)?,
+    };
+    // Some Windows editors save a byte order mark, which hides the opening `---`
+    let bom = if input.starts_with('\u{feff}') { '\u{feff}'.len_utf8() } else { 0 };
+    let input = &input[bom..];
+    let expanded;
+    let input = if expand_includes {
+        expanded = transform::expand_includes(input, mdfile)?;
+        expanded.as_str()
+    } else {
+        input
+    };
+
+    let body_only = if overrides.supplied_text.is_some() {
+        // Quick notes often have no front matter at all
+        transform::split_front_matter(input).0.is_empty()
+    } else {
+        mdfile == Path::new("-") && overrides.stdin_format == StdinFormat::Body
+    };
+    let (mut metadata, content, content_offset, front_matter_keys) = if body_only {
+        (PublishMetadata::default(), input.to_string(), bom, Vec::new())
+    } else {
+        let document: Document<PublishMetadata> = YamlFrontMatter::parse::<PublishMetadata>(input)
+            .map_err(|err| anyhow!("Invalid front matter: {}", err))?;
+        let keys = YamlFrontMatter::parse::<serde_json::Map<String, serde_json::Value>>(input)
+            .map(|document| document.metadata.keys().cloned().collect())
+            .unwrap_or_default();
+        (document.metadata, document.content, bom + content_offset(input), keys)
+    };
+
+    if let Some(ref defaults) = overrides.defaults {
+        defaults.apply(&mut metadata)?;
+    }
+    if let (None, Some(source_url)) = (&metadata.canonical_url, &overrides.source_url) {
+        metadata.canonical_url = Some(source_url.to_string());
     }
 
-    Ok(())
+    if let Some(ref title) = overrides.title {
+        metadata.title = title.to_owned();
+    }
+    if let Some(ref tags) = overrides.tags {
+        metadata.tags = Some(tags.to_owned());
+    }
+    if let Some(ref status) = overrides.status {
+        metadata.status = Some(status.to_owned());
+    }
+    if let Some(ref in_response_to) = overrides.in_response_to {
+        metadata.in_response_to = Some(in_response_to.to_owned());
+    }
+    Ok(ParsedPost {
+        metadata,
+        content,
+        content_offset,
+        front_matter_keys,
+    })
+}
+
+/// Whether `scheduled_for` is still to come, which makes the post a draft
+fn scheduled_in_future(mdfile: &Path, metadata: &PublishMetadata) -> Result<bool> {
+    let Some(ref scheduled_for) = metadata.scheduled_for else {
+        return Ok(false);
+    };
+    let time = if scheduled_for.len() == 10 {
+        humantime::parse_rfc3339_weak(&format!("{} 00:00:00", scheduled_for))
+    } else {
+        humantime::parse_rfc3339_weak(scheduled_for)
+    }
+    .map_err(|err| anyhow!("{}: invalid scheduled_for {:?}: {}", mdfile.display(), scheduled_for, err))?;
+    Ok(time > SystemTime::now())
+}
+
+/// Read a post's front matter and transformed body, without the footer
+fn parse_post(mdfile: PathBuf, post: &PostOptions) -> Result<PublishMetadata, anyhow::Error> {
+    let started = metrics::start();
+    let parsed = parse_metadata(&mdfile, &post.metadata, post.transform.expand_includes)?;
+    metrics::record(metrics::Phase::Parse, started);
+    prepare_post(&mdfile, parsed, post)
+}
+
+/// Check a parsed post and transform its body
+fn prepare_post(mdfile: &Path, parsed: ParsedPost, post: &PostOptions) -> Result<PublishMetadata, anyhow::Error> {
+    let ParsedPost {
+        mut metadata, content, ..
+    } = parsed;
+    if metadata.title.trim().is_empty() {
+        return Err(anyhow!("{}: a title is required, in front matter or with --title", mdfile.display()));
+    }
+
+    if scheduled_in_future(mdfile, &metadata)? {
+        errln!(
+            "Warning: Medium's API cannot schedule posts; publishing {} now as a draft. \
+             Publish it from Medium at {}.",
+            mdfile.display(),
+            metadata.scheduled_for.as_deref().unwrap_or_default()
+        );
+        metadata.status = Some(PublishStatus::Draft);
+    }
+
+    let started = metrics::start();
+    metadata.content = post.transform.apply(content, &metadata.content_format);
+    metrics::record(metrics::Phase::Transform, started);
+
+    Ok(metadata)
+}
+
+/// A table of every metadata field with its final value and where that
+/// came from: a flag, the front matter, the directory's `_defaults` or the
+/// built-in default, in that order of precedence
+fn resolved_metadata_report(mdfile: &Path, parsed: &ParsedPost, overrides: &MetadataOptions) -> Result<String> {
+    let metadata = &parsed.metadata;
+    let scheduled = scheduled_in_future(mdfile, metadata)?;
+    let in_front_matter = |key: &str| parsed.front_matter_keys.iter().any(|found| found == key);
+    let in_defaults = |key: &str| overrides.defaults.as_ref().is_some_and(|defaults| defaults.sets(key));
+    let text = |value: serde_json::Value| match value {
+        serde_json::Value::Null => "-".to_string(),
+        serde_json::Value::String(text) => text,
+        serde_json::Value::Array(items) => items
+            .into_iter()
+            .map(|item| item.as_str().map(str::to_string).unwrap_or_else(|| item.to_string()))
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    };
+
+    let status = match (&metadata.status, scheduled) {
+        (_, true) => "draft".to_string(),
+        (Some(status), false) => status.to_string(),
+        (None, false) => "- (Medium publishes it publicly)".to_string(),
+    };
+    let fields = [
+        ("title", overrides.title.is_some(), text(serde_json::json!(metadata.title))),
+        ("content_format", false, metadata.content_format.clone()),
+        ("tags", overrides.tags.is_some(), text(serde_json::json!(metadata.tags))),
+        ("canonical_url", false, text(serde_json::json!(metadata.canonical_url))),
+        ("status", overrides.status.is_some(), status),
+        ("notify_followers", false, text(serde_json::json!(metadata.notify_followers))),
+        ("publish", false, text(serde_json::json!(metadata.publish))),
+        ("slug", false, metadata.slug()),
+        ("series", false, text(serde_json::to_value(&metadata.series)?)),
+        ("cover", false, text(serde_json::json!(metadata.cover))),
+        ("scheduled_for", false, text(serde_json::json!(metadata.scheduled_for))),
+        ("in_response_to", overrides.in_response_to.is_some(), text(serde_json::json!(metadata.in_response_to))),
+        ("content_warning", false, text(serde_json::json!(metadata.content_warning))),
+        ("aliases", false, text(serde_json::json!(metadata.aliases))),
+    ];
+
+    let rows: Vec<[String; 3]> = fields
+        .into_iter()
+        .map(|(key, flagged, value)| {
+            // A future schedule makes a draft whatever the flags say
+            let source = if key == "status" && scheduled {
+                "scheduled_for"
+            } else if flagged {
+                "flag"
+            } else if key == "tags" && in_front_matter(key) && in_defaults(key) {
+                "front matter, _defaults"
+            } else if in_front_matter(key) {
+                "front matter"
+            } else if in_defaults(key) {
+                "_defaults"
+            } else if key == "slug" {
+                "title"
+            } else {
+                "default"
+            };
+            [key.to_string(), source.to_string(), value]
+        })
+        .collect();
+
+    let width = |column: usize| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0);
+    let (key_width, source_width) = (width(0), width(1));
+    Ok(rows
+        .iter()
+        .map(|row| format!("{:key_width$}  {:source_width$}  {}\n", row[0], row[1], row[2]))
+        .collect())
+}
+
+/// The cover image as the first element of the content
+fn cover_image(src: &str, content_format: &str) -> String {
+    if content_format == "html" {
+        format!("<img src=\"{}\">\n", escape_html(src))
+    } else if src.contains(char::is_whitespace) {
+        format!("![](<{}>)\n\n", src)
+    } else {
+        format!("![]({})\n\n", src)
+    }
+}
+
+/// A blockquote warning readers about what the post covers
+fn content_warning_note(warning: &str, content_format: &str) -> String {
+    if content_format == "html" {
+        format!(
+            "<blockquote><strong>⚠️ Content warning:</strong> {}</blockquote>\n",
+            escape_html(warning.trim())
+        )
+    } else {
+        format!("> ⚠️ **Content warning:** {}\n\n", warning.trim().replace('\n', "\n> "))
+    }
+}
+
+/// Says the post came from `moved_from` on Medium, after the footer naming its new home
+fn moved_note(moved_from: &str, content_format: &str) -> String {
+    if content_format == "html" {
+        format!(
+            "\n<p><em>It first appeared <a href=\"{}\">on Medium</a> and has since moved there.</em></p>",
+            escape_html(moved_from)
+        )
+    } else {
+        format!("\n\n*It first appeared [on Medium]({}) and has since moved there.*", moved_from)
+    }
+}
+
+fn starts_with_image(content: &str, content_format: &str) -> bool {
+    let content = content.trim_start();
+    if content_format == "html" {
+        content.starts_with("<img") || content.starts_with("<figure")
+    } else {
+        content.starts_with("![")
+    }
+}
+
+/// Add the cover, content warning and canonical footer, then run the
+/// final `--pipe` step
+fn finish_post(metadata: &mut PublishMetadata, transform: &TransformOptions) -> Result<(), anyhow::Error> {
+    let starts_with_image = starts_with_image(&metadata.content, &metadata.content_format);
+    if let Some(ref warning) = metadata.content_warning {
+        // Added before the cover so the cover stays the first image
+        metadata.content = content_warning_note(warning, &metadata.content_format) + metadata.content.trim_start();
+    }
+
+    if let Some(ref cover) = metadata.cover {
+        if starts_with_image {
+            errln!("Warning: the content already starts with an image, so the cover may appear twice");
+        }
+        metadata.content = cover_image(cover, &metadata.content_format) + metadata.content.trim_start();
+    }
+
+    if let Some(canonical_url) = metadata.canonical_url.as_ref().filter(|_| !transform.no_footer) {
+        // Add the "Originally published at XXX"
+        let separator = transform.footer_separator.as_deref();
+        metadata.content += get_canonical_reference(canonical_url.to_string(), &metadata.content_format, separator)?.as_str();
+        if let Some(ref moved_from) = transform.moved_from {
+            metadata.content += &moved_note(moved_from, &metadata.content_format);
+        }
+    }
+
+    metadata.content = transform.replace_all(&metadata.content)?;
+
+    if let Some(ref command) = transform.pipe {
+        metadata.content = transform::pipe_through(command, &metadata.content)?;
+    }
+
+    Ok(())
+}
+
+fn assemble_post(mdfile: PathBuf, post: &PostOptions) -> Result<PublishMetadata, anyhow::Error> {
+    let mut metadata = parse_post(mdfile, post)?;
+    finish_post(&mut metadata, &post.transform)?;
+    Ok(metadata)
+}
+
+/// Metadata under its front matter names, leaving out fields that are
+/// unset so a schema's `required` works as expected
+fn front_matter_json(metadata: &PublishMetadata) -> serde_json::Value {
+    let fields = serde_json::json!({
+        "title": metadata.title,
+        "content_format": metadata.content_format,
+        "tags": metadata.tags,
+        "canonical_url": metadata.canonical_url,
+        "status": metadata.status,
+        "publish": metadata.publish,
+        "slug": metadata.slug,
+        "series": metadata.series,
+        "cover": metadata.cover,
+        "notify_followers": metadata.notify_followers,
+        "scheduled_for": metadata.scheduled_for,
+        "in_response_to": metadata.in_response_to,
+        "content_warning": metadata.content_warning,
+        "aliases": metadata.aliases,
+    });
+    let mut fields = fields.as_object().cloned().unwrap_or_default();
+    fields.retain(|_, value| !value.is_null());
+    serde_json::Value::Object(fields)
+}
+
+/// Refuse metadata that breaks the JSON schema at `path`
+fn check_schema(
+    mdfile: &Path,
+    metadata: &PublishMetadata,
+    path: &Path,
+    diagnostics: &mut validate::Diagnostics,
+) -> Result<()> {
+    let validator = schema::load(path)?;
+    let problems = schema::validate(&validator, &front_matter_json(metadata));
+    if !problems.is_empty() {
+        return diagnostics.error(format!(
+            "{}: front matter doesn't match {}:\n  {}",
+            mdfile.display(),
+            path.display(),
+            problems.join("\n  ")
+        ));
+    }
+    Ok(())
+}
+
+/// Every metadata field as interpreted, including ones never sent to Medium
+fn inspect(mdfile: &Path, overrides: &MetadataOptions) -> Result<serde_json::Value> {
+    let parsed = parse_metadata(mdfile, overrides, false)?;
+    let metadata = parsed.metadata;
+    Ok(serde_json::json!({
+        "title": metadata.title,
+        "contentFormat": metadata.content_format,
+        "tags": metadata.tags,
+        "canonicalUrl": metadata.canonical_url,
+        "publishStatus": metadata.status,
+        "publish": metadata.publish,
+        "slug": metadata.slug(),
+        "series": metadata.series,
+        "cover": metadata.cover,
+        "notifyFollowers": metadata.notify_followers,
+        "scheduledFor": metadata.scheduled_for,
+        "inResponseToPostId": metadata.in_response_to,
+        "contentWarning": metadata.content_warning,
+        "aliases": metadata.aliases,
+        "contentOffset": parsed.content_offset,
+    }))
+}
+
+/// One CSV line, quoting fields that contain commas, quotes or newlines
+fn csv_row(fields: &[String]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect();
+    fields.join(",") + "\n"
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_html(metadata: &PublishMetadata) -> String {
+    let body = if metadata.content_format == "html" {
+        metadata.content.clone()
+    } else {
+        let parser = pulldown_cmark::Parser::new(&metadata.content);
+        let mut body = String::new();
+        pulldown_cmark::html::push_html(&mut body, parser);
+        body
+    };
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape_html(&metadata.title),
+        body = body
+    )
+}
+
+fn preview(mdfile: PathBuf, post: &PostOptions) -> Result<PathBuf, anyhow::Error> {
+    // Hash the full path so posts sharing a file name don't overwrite each other's preview
+    let mut hasher = DefaultHasher::new();
+    std::fs::canonicalize(&mdfile).unwrap_or_else(|_| mdfile.clone()).hash(&mut hasher);
+    let metadata = assemble_post(mdfile, post)?;
+
+    let file_path = std::env::temp_dir().join(format!(
+        "markmedium-preview-{}-{:016x}.html",
+        metadata.slug(),
+        hasher.finish()
+    ));
+    std::fs::write(&file_path, render_html(&metadata))?;
+    if let Err(err) = open::that(&file_path) {
+        errln!("Warning: could not open a browser: {}", err);
+    }
+
+    Ok(file_path)
+}
+
+fn render_export(metadata: &PublishMetadata) -> String {
+    format!("# {}\n\n{}\n", metadata.title, metadata.content.trim())
+}
+
+fn export(path: &Path, output: Option<&Path>, post: &PostOptions) -> Result<Vec<PathBuf>> {
+    let files = if path.is_dir() {
+        batch::markdown_files(path)?
+            .into_iter()
+            .map(|file| (path.join(&file), file))
+            .collect()
+    } else {
+        let name = path.file_name().ok_or_else(|| anyhow!("Not a file: {}", path.display()))?;
+        vec![(path.to_path_buf(), PathBuf::from(name))]
+    };
+
+    let output = match output {
+        Some(output) => output,
+        None if path.is_dir() => return Err(anyhow!("--output is required when exporting a directory")),
+        None => {
+            out!("{}", render_export(&assemble_post(path.to_path_buf(), post)?));
+            return Ok(Vec::new());
+        }
+    };
+
+    let mut written = Vec::new();
+    for (source, relative) in files {
+        let metadata = assemble_post(source, post)?;
+        let target = output.join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&target, render_export(&metadata))?;
+        written.push(target);
+    }
+
+    Ok(written)
+}
+
+/// Assemble `mdfile` offline, warning about images that still point at local files
+fn compile(mdfile: &Path, output: Option<&Path>, post: &PostOptions) -> Result<()> {
+    let metadata = assemble_post(mdfile.to_path_buf(), post)?;
+    let mut local: Vec<String> = if metadata.content_format == "markdown" {
+        images::image_links(&metadata.content).into_iter().map(|link| link.src).collect()
+    } else {
+        // Only the cover is known to be an image in HTML
+        metadata.cover.clone().into_iter().collect()
+    };
+    local.retain(|src| images::is_local(src));
+    local.sort();
+    local.dedup();
+    for src in &local {
+        errln!("Warning: local image {} must be uploaded by hand after importing", src);
+    }
+
+    match output {
+        Some(output) => std::fs::write(output, render_export(&metadata))?,
+        None => out!("{}", render_export(&metadata)),
+    }
+    Ok(())
+}
+
+async fn check_reachable(client: &Client, url: &str) -> Result<()> {
+    let response = client
+        .send(client.head(url).timeout(Duration::from_secs(10)))
+        .await?;
+    if response.status().is_client_error() || response.status().is_server_error() {
+        return Err(anyhow!("HTTP {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Fill in tags suggested from the content when the post has none,
+/// asking first if `interactive`
+fn add_auto_tags(metadata: &mut PublishMetadata, interactive: bool) -> Result<()> {
+    if metadata.tags.as_ref().is_some_and(|tags| !tags.is_empty()) {
+        return Ok(());
+    }
+    let suggested = transform::suggest_tags(&metadata.content, &metadata.content_format);
+    if suggested.is_empty() {
+        return Ok(());
+    }
+    let question = format!("Tag \"{}\" with {}?", metadata.title, suggested.join(", "));
+    if !interactive || confirm(&question)? {
+        metadata.tags = Some(suggested);
+    }
+    Ok(())
+}
+
+/// Warn about a title Medium would cut off, refusing it with --strict,
+/// or shorten it with --truncate-title
+fn check_title(
+    mdfile: &Path,
+    metadata: &mut PublishMetadata,
+    options: &PublishArgs,
+    diagnostics: &mut validate::Diagnostics,
+) -> Result<()> {
+    let length = metadata.title.chars().count();
+    if length <= validate::MAX_TITLE_LENGTH {
+        return Ok(());
+    }
+    if options.truncate_title {
+        metadata.title = validate::truncate_title(&metadata.title);
+        errln!(
+            "Warning: {}: title truncated from {} to {} characters",
+            mdfile.display(),
+            length,
+            metadata.title.chars().count()
+        );
+        return Ok(());
+    }
+    let message = format!(
+        "{}: the title is {} characters, over Medium's limit of {}; shorten it or use --truncate-title",
+        mdfile.display(),
+        length,
+        validate::MAX_TITLE_LENGTH
+    );
+    if options.strict {
+        return diagnostics.error(message);
+    }
+    errln!("Warning: {}", message);
+    Ok(())
+}
+
+/// Apply `--add-tag-all`, skipping tags the post has and stopping at Medium's limit
+fn add_batch_tags(mdfile: &Path, metadata: &mut PublishMetadata, batch_tags: &[String]) {
+    for tag in batch_tags {
+        let tags = metadata.tags.get_or_insert_with(Vec::new);
+        if validate::has_tag(tags, tag) {
+            continue;
+        }
+        if tags.len() >= validate::MAX_TAGS {
+            errln!(
+                "Warning: {} already has {} tags, so {} was not added",
+                mdfile.display(),
+                tags.len(),
+                tag
+            );
+            continue;
+        }
+        tags.push(tag.to_owned());
+    }
+}
+
+/// Print what `publish` would send for each file, without creating anything
+async fn dry_run(options: &PublishArgs, client_options: &ClientOptions) -> Result<()> {
+    let config = load_config();
+
+    if options.validate_remote {
+        let config = config.as_ref().map_err(|err| anyhow!("Could not read config: {}", err))?;
+        let client = client_options.build(config.user_agent.as_deref())?;
+        let user = fetch_user(&client, &config.token).await?;
+        outln!("Token is valid for author {}", user.id);
+    }
+
+    let files = if options.file().is_dir() {
+        let sort = options.sort.unwrap_or(batch::Sort::Name);
+        batch::sort_files(options.file(), batch::markdown_files(options.file())?, sort)?
+            .into_iter()
+            .map(|file| options.file().join(file))
+            .collect()
+    } else {
+        vec![options.file().to_owned()]
+    };
+
+    let author_id = match (&options.author_id, &config) {
+        (Some(author_id), _) => author_id.as_str(),
+        (None, Ok(config)) => config.id.as_str(),
+        (None, Err(_)) => "<author id>",
+    };
+
+    for file in files {
+        let parsed = parse_metadata(&file, &options.post.metadata, options.post.transform.expand_includes)?;
+        let sources = match options.show_sources {
+            true => Some(resolved_metadata_report(&file, &parsed, &options.post.metadata)?),
+            false => None,
+        };
+        let mut metadata = prepare_post(&file, parsed, &options.post)?;
+        if options.auto_tags {
+            add_auto_tags(&mut metadata, false)?;
+        }
+        finish_post(&mut metadata, &options.post.transform)?;
+        let mut diagnostics = validate::Diagnostics::new(options.collect_errors);
+        check_title(&file, &mut metadata, options, &mut diagnostics)?;
+        add_batch_tags(&file, &mut metadata, &options.add_tag_all);
+        let schema = match &config {
+            Ok(config) => options.schema(config),
+            Err(_) => options.schema.as_deref(),
+        };
+        if let Some(path) = schema {
+            check_schema(&file, &metadata, path, &mut diagnostics)?;
+        }
+        diagnostics.finish(&file)?;
+        outln!("# {}", file.display());
+        if let Some(sources) = sources {
+            out!("{}", sources);
+        }
+        let url = posts_url(author_id, options.publication_id.as_deref());
+        if options.print_curl {
+            let token = match &config {
+                Ok(config) if options.insecure_print_token => config.token.as_str(),
+                _ => "<token>",
+            };
+            outln!("{}", curl_command(&url, token, &metadata)?);
+        } else {
+            if let Some(limit) = options.preview_chars {
+                if let Some((end, _)) = metadata.content.char_indices().nth(limit) {
+                    metadata.content.truncate(end);
+                    metadata.content.push('…');
+                }
+            }
+            outln!("POST {}", url);
+            outln!("{}", serde_json::to_string_pretty(&metadata)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Where a post is created: the author's profile, or a publication they write for
+fn posts_url(author_id: &str, publication_id: Option<&str>) -> String {
+    match publication_id {
+        Some(publication_id) => format!("https://api.medium.com/v1/publications/{}/posts", publication_id),
+        None => format!("https://api.medium.com/v1/users/{}/posts", author_id),
+    }
+}
+
+/// Name of a publication `author_id` belongs to, from their publications list
+async fn publication_name(client: &Client, config: &ApiConfig, author_id: &str, publication_id: &str) -> Result<String> {
+    let response = client
+        .send(
+            client
+                .get(&format!("https://api.medium.com/v1/users/{}/publications", author_id))
+                .bearer_auth(&config.token),
+        )
+        .await?;
+    let publications: PublicationsResponse = read_response(response).await?;
+    publications
+        .data
+        .into_iter()
+        .find(|publication| publication.id == publication_id)
+        .map(|publication| publication.name)
+        .ok_or_else(|| anyhow!("{} is not one of your publications", publication_id))
+}
+
+async fn send_post(
+    client: &Client,
+    config: &ApiConfig,
+    url: &str,
+    metadata: &PublishMetadata,
+) -> Result<PublishResponse, anyhow::Error> {
+    let response: reqwest::Response = client
+        .send(
+            client
+                .post(url)
+                .bearer_auth(&config.token)
+                .json(metadata),
+        )
+        .await?;
+    read_response(response).await
+}
+
+/// How `submit` treats the request it is about to send
+#[derive(Default)]
+struct SubmitOptions {
+    /// Send even if an identical request's outcome is unknown
+    force: bool,
+    /// Print the request as a `curl` command first
+    print_curl: bool,
+    /// Put the real token in that command rather than a placeholder
+    print_token: bool,
+    /// Publish with placeholders for images that fail to upload
+    skip_failed_images: bool,
+    /// Leave local images as they are instead of uploading them
+    no_image_uploads: bool,
+}
+
+/// Quote `text` for a POSIX shell
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// An equivalent `curl` invocation of the publish request, for reproducing it by hand
+fn curl_command(url: &str, token: &str, metadata: &PublishMetadata) -> Result<String> {
+    Ok(format!(
+        "curl -X POST {} -H {} -H {} --data-raw {}",
+        shell_quote(url),
+        shell_quote(&format!("Authorization: Bearer {}", token)),
+        shell_quote("Content-Type: application/json"),
+        shell_quote(&serde_json::to_string(metadata)?)
+    ))
+}
+
+/// What identifies a publish request for the pending marker
+fn publish_request(url: &str, metadata: &PublishMetadata) -> Result<String> {
+    Ok(format!("{}\n{}", url, serde_json::to_string(metadata)?))
+}
+
+/// Upload the post's local images, or for HTML just its cover, pointing the content at them
+async fn upload_images(
+    client: &Client,
+    config: &ApiConfig,
+    base_dir: &Path,
+    metadata: &mut PublishMetadata,
+    submit_options: &SubmitOptions,
+) -> Result<(), anyhow::Error> {
+    let upload = !submit_options.no_image_uploads;
+    if upload && metadata.content_format == "markdown" {
+        metadata.content = images::upload_local_images(
+            client,
+            &config.token,
+            base_dir,
+            &metadata.content,
+            submit_options.skip_failed_images,
+        )
+        .await?;
+    } else if let Some(cover) = metadata.cover.as_deref().filter(|cover| upload && images::is_local(cover)) {
+        // HTML images aren't uploaded, but the cover was added by us and sits first
+        match images::upload_image(client, &config.token, &base_dir.join(cover)).await {
+            Ok(url) => metadata.content = metadata.content.replacen(&escape_html(cover), &url, 1),
+            Err(err) if submit_options.skip_failed_images => {
+                errln!("Warning: could not upload cover {}, publishing without it: {}", cover, err);
+                metadata.content = metadata.content.replacen(&cover_image(cover, "html"), "", 1);
+            }
+            Err(err) => return Err(anyhow!("Could not upload cover {}: {}", cover, err)),
+        }
+    }
+    Ok(())
+}
+
+/// Upload local images, send the post and record it in the history,
+/// returning what was recorded. `source` is the canonical path of the file it came from.
+async fn submit(
+    client: &Client,
+    config: &ApiConfig,
+    author_id: &str,
+    publication_id: Option<&str>,
+    source: PathBuf,
+    mut metadata: PublishMetadata,
+    submit_options: SubmitOptions,
+) -> Result<history::HistoryEntry, anyhow::Error> {
+    let base_dir = source.parent().unwrap_or_else(|| Path::new("."));
+    // What --diff compares against next time, which has local images as written
+    let snapshot = metadata.content.clone();
+
+    // Keyed by the request before image uploads, whose results can differ
+    // between attempts, and checked before uploading anything again
+    let url = posts_url(author_id, publication_id);
+    let request = publish_request(&url, &metadata)?;
+    if history::begin_attempt(&request)? && !submit_options.force {
+        return Err(anyhow!(
+            "A previous attempt to publish this content may have succeeded; check your Medium account, \
+             then rerun with --force to publish anyway"
+        ));
+    }
+
+    let started = metrics::start();
+    let uploaded = upload_images(client, config, base_dir, &mut metadata, &submit_options).await;
+    metrics::record(metrics::Phase::Upload, started);
+    if let Err(err) = uploaded {
+        // Nothing was posted, so a retry is safe
+        if let Err(err) = history::finish_attempt(&request) {
+            errln!("Warning: could not clear the pending publish marker: {}", err);
+        }
+        return Err(err);
+    }
+
+    if submit_options.print_curl {
+        let token = if submit_options.print_token { config.token.as_str() } else { "<token>" };
+        outln!("{}", curl_command(&url, token, &metadata)?);
+    }
+
+    let started = metrics::start();
+    let result = send_post(client, config, &url, &metadata).await;
+    metrics::record(metrics::Phase::Publish, started);
+    // Only a request that never connected, or one Medium answered, has a known outcome
+    let known_outcome = match &result {
+        Ok(_) => true,
+        Err(err) => {
+            err.downcast_ref::<ApiError>().is_some()
+                || err
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(|err| err.is_connect() || err.is_builder())
+        }
+    };
+    if known_outcome {
+        if let Err(err) = history::finish_attempt(&request) {
+            errln!("Warning: could not clear the pending publish marker: {}", err);
+        }
+    }
+    let publish_response = result?;
+    let publish_data = publish_response.data;
+    if let Err(err) = history::save_snapshot(&source, &snapshot) {
+        errln!("Warning: could not save a snapshot for --diff: {}", err);
+    }
+    let mut entry = history::HistoryEntry::new(
+        source,
+        metadata.slug(),
+        metadata.title,
+        author_id.to_string(),
+        publish_data.id,
+        publish_data.url.clone(),
+        // Medium publishes publicly when no status is given
+        publish_data
+            .publish_status
+            .or(metadata.status)
+            .unwrap_or(PublishStatus::Public),
+    );
+    entry.series = metadata.series;
+    entry.aliases = metadata.aliases.unwrap_or_default();
+    if let Err(err) = history::record(&entry) {
+        errln!("Warning: could not record publishing history: {}", err);
+    }
+    Ok(entry)
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(file = %mdfile.display())))]
+async fn publish(
+    mdfile: PathBuf,
+    options: &PublishArgs,
+    client: &Client,
+    config: &ApiConfig,
+) -> Result<history::HistoryEntry, anyhow::Error> {
+    metrics::begin(mdfile.display().to_string());
+    match parse_post(mdfile.clone(), &options.post) {
+        Ok(metadata) => publish_post(mdfile, metadata, options, client, config).await,
+        Err(err) => {
+            if let Some(webhook) = options.webhook(config) {
+                webhook::notify(client, webhook, &mdfile, None, Err(&err)).await;
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Publish `mdfile` once per `--profiles` entry, pointing later copies back
+/// at the first that succeeds. Failures don't stop the remaining profiles.
+async fn publish_profiles(
+    mdfile: PathBuf,
+    options: &PublishArgs,
+    client_options: &ClientOptions,
+    config: &ApiConfig,
+) -> Vec<(String, Result<String, anyhow::Error>)> {
+    let mut first_url: Option<String> = None;
+    let mut results = Vec::new();
+
+    for name in &options.profiles {
+        metrics::begin(format!("{} ({})", mdfile.display(), name));
+        let result = async {
+            let config = config.profile(name)?;
+            let client = client_options.build(config.user_agent.as_deref())?;
+            let mut metadata = parse_post(mdfile.clone(), &options.post)?;
+            if metadata.canonical_url.is_none() {
+                metadata.canonical_url = first_url.clone();
+            }
+            Ok(publish_post(mdfile.clone(), metadata, options, &client, &config).await?.url)
+        }
+        .await;
+
+        match &result {
+            Ok(url) => {
+                outln!("Published to {} at {}", name, url);
+                first_url.get_or_insert_with(|| url.clone());
+            }
+            Err(err) => errln!("Failed to publish to {}: {}", name, err),
+        }
+        results.push((name.clone(), result));
+    }
+
+    results
+}
+
+/// Publish each part of `mdfile` between `--split-on` markers as its own
+/// post, titled by its first heading
+async fn publish_split(
+    mdfile: PathBuf,
+    marker: &str,
+    options: &PublishArgs,
+    client: &Client,
+    config: &ApiConfig,
+) -> Result<Vec<String>, anyhow::Error> {
+    metrics::begin(mdfile.display().to_string());
+    let metadata = parse_post(mdfile.clone(), &options.post)?;
+    let segments = transform::split_segments(&metadata.content, marker);
+    let mut urls = Vec::new();
+
+    for (index, content) in segments.into_iter().enumerate() {
+        let mut part = if index == 0 || options.split_metadata == SplitMetadata::All {
+            metadata.clone()
+        } else {
+            PublishMetadata {
+                content_format: metadata.content_format.clone(),
+                ..PublishMetadata::default()
+            }
+        };
+        part.title = match transform::first_heading(&content, &part.content_format) {
+            Some(heading) => heading,
+            None => format!("{} (part {})", metadata.title, index + 1),
+        };
+        if index > 0 {
+            // A slug from front matter names the first part only, and old
+            // URLs redirect to the start of the post
+            part.slug = None;
+            part.aliases = None;
+        }
+        part.content = content;
+        let title = part.title.clone();
+        let url = publish_post(mdfile.clone(), part, options, client, config).await?.url;
+        // Shown as each part goes out, so a later failure doesn't hide them
+        outln!("Published \"{}\" at {}", title, url);
+        urls.push(url);
+    }
+
+    Ok(urls)
+}
+
+/// Check, finish and send a parsed post, then report the outcome to any webhook
+async fn publish_post(
+    mdfile: PathBuf,
+    metadata: PublishMetadata,
+    options: &PublishArgs,
+    client: &Client,
+    config: &ApiConfig,
+) -> Result<history::HistoryEntry, anyhow::Error> {
+    let title = metadata.title.clone();
+    let result = check_and_submit(mdfile.clone(), metadata, options, client, config).await;
+    if let Some(webhook) = options.webhook(config) {
+        let outcome = result.as_ref().map(|entry| entry.url.as_str());
+        webhook::notify(client, webhook, &mdfile, Some(&title), outcome).await;
+    }
+    result
+}
+
+async fn check_and_submit(
+    mdfile: PathBuf,
+    mut metadata: PublishMetadata,
+    options: &PublishArgs,
+    client: &Client,
+    config: &ApiConfig,
+) -> Result<history::HistoryEntry, anyhow::Error> {
+    let mut diagnostics = validate::Diagnostics::new(options.collect_errors);
+    // Checked before the footer is added, since a footer alone isn't a post
+    if metadata.content.trim().is_empty() && !options.allow_empty {
+        diagnostics.error(format!("{}: content is empty", mdfile.display()))?;
+    }
+    if options.auto_tags {
+        // Suggested from the body alone, before the footer is added
+        add_auto_tags(&mut metadata, !options.yes && std::io::stdin().is_terminal())?;
+    }
+    let started = metrics::start();
+    finish_post(&mut metadata, &options.post.transform)?;
+    metrics::record(metrics::Phase::Transform, started);
+    let author_id = options.author_id.as_ref().unwrap_or(&config.id);
+    let source = std::fs::canonicalize(&mdfile).unwrap_or_else(|_| mdfile.clone());
+
+    if options.diff {
+        match history::last_snapshot(&source)? {
+            Some(previous) => {
+                let diff = similar::TextDiff::from_lines(&previous, &metadata.content);
+                out!("{}", diff.unified_diff().header("last published", "current"));
+            }
+            None => outln!("{} has not been published before", source.display()),
+        }
+        if !confirm("Continue publishing?")? {
+            return Err(anyhow!("Publishing cancelled"));
+        }
+    }
+
+    let is_draft = matches!(metadata.status, Some(PublishStatus::Draft));
+    if (options.warn_markers || options.strict) && !is_draft {
+        let hits = validate::find_markers(&metadata.content, &options.markers);
+        for hit in &hits {
+            errln!("Warning: {} found on line {} of the content", hit.marker, hit.line);
+        }
+        if options.strict && !hits.is_empty() {
+            diagnostics.error("Content still contains markers; fix them or publish as a draft")?;
+        }
+    }
+
+    check_title(&mdfile, &mut metadata, options, &mut diagnostics)?;
+
+    if options.warn_math {
+        let lines = validate::find_math(&metadata.content, &metadata.content_format);
+        for line in &lines {
+            errln!("Warning: math on line {} of the content won't render on Medium", line);
+        }
+        if options.strict && !lines.is_empty() {
+            diagnostics.error("Content contains math that Medium can't render")?;
+        }
+    }
+
+    if options.render_check && metadata.content_format == "markdown" {
+        let warnings = validate::render_check(&metadata.content);
+        for warning in &warnings {
+            errln!("Warning: line {} of the content: {}", warning.line, warning.message);
+        }
+        if options.strict && !warnings.is_empty() {
+            diagnostics.error("Content has markdown that won't render as intended")?;
+        }
+    }
+
+    if options.warn_external_images && metadata.content_format == "markdown" {
+        let sources = images::insecure_images(&metadata.content);
+        for src in &sources {
+            errln!("Warning: image {} isn't loaded over HTTPS; use an https:// URL", src);
+        }
+        if options.strict && !sources.is_empty() {
+            diagnostics.error("Content has images that aren't loaded over HTTPS")?;
+        }
+    }
+
+    add_batch_tags(&mdfile, &mut metadata, &options.add_tag_all);
+
+    if let Some(tags) = metadata.tags.as_mut() {
+        if options.split_tag_commas {
+            *tags = validate::split_tag_commas(tags);
+        }
+        let problems: Vec<String> = tags
+            .iter()
+            .filter_map(|tag| validate::tag_problem(tag).map(|problem| format!("\n  {:?} {}", tag, problem)))
+            .collect();
+        if !problems.is_empty() {
+            diagnostics.error(format!("{}: invalid tags:{}", mdfile.display(), problems.concat()))?;
+        }
+    }
+
+    if let Some(path) = options.schema(config) {
+        check_schema(&mdfile, &metadata, path, &mut diagnostics)?;
+    }
+
+    if let Some(canonical_url) = metadata.canonical_url.as_ref().filter(|_| options.check_canonical) {
+        // A typo here sends readers nowhere, but an unreachable host shouldn't block publishing
+        if let Err(err) = check_reachable(client, canonical_url).await {
+            errln!("Warning: canonical URL {} may not be reachable: {}", canonical_url, err);
+        }
+    }
+
+    if let (Some(vocabulary), Some(tags)) = (&options.tags_file, &metadata.tags) {
+        let unknown = vocabulary.unknown(tags);
+        if !unknown.is_empty() {
+            let message = format!("Tags not in the allowed list: {}", unknown.join(", "));
+            if options.strict {
+                diagnostics.error(message)?;
+            } else {
+                errln!("Warning: {}", message);
+            }
+        }
+    }
+    diagnostics.finish(&mdfile)?;
+
+    if let Some(publication_id) = &options.publication_id {
+        // Publication posts reach its whole audience, so make sure it's intended
+        if !options.yes {
+            if !std::io::stdin().is_terminal() {
+                return Err(anyhow!("Publishing to a publication needs --yes when not run interactively"));
+            }
+            let name = publication_name(client, config, author_id, publication_id).await?;
+            if !confirm(&format!("Publish \"{}\" to the publication {}?", metadata.title, name))? {
+                return Err(anyhow!("Publishing cancelled"));
+            }
+        }
+    }
+
+    if let Some(moved_from) = &options.republish_as_canonical {
+        check_republish(&mdfile, &metadata, moved_from)?;
+    }
+
+    if options.retry_idempotency_check {
+        check_duplicate_title(client, config, &metadata.title, options.yes).await?;
+    }
+
+    let publication_id = options.publication_id.as_deref();
+    let submit_options = SubmitOptions {
+        force: options.force,
+        print_curl: options.print_curl,
+        print_token: options.insecure_print_token,
+        skip_failed_images: options.skip_failed_images,
+        no_image_uploads: options.safe_mode,
+    };
+    submit(client, config, author_id, publication_id, source, metadata, submit_options).await
+}
+
+#[tokio::main]
+async fn main() -> std::process::ExitCode {
+    let result = run().await;
+    metrics::print_summary();
+    match result {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) if err.is::<AlreadyReported>() => std::process::ExitCode::FAILURE,
+        Err(err) => {
+            errln!("{} {:?}", color::error("Error:"), err);
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = Args::parse();
+    color::init(args.no_color, args.json);
+    if let Some(config) = &args.config {
+        let _ = CONFIG_PATH.set(config.to_owned());
+    }
+    if let Some(log_file) = &args.log_file {
+        tee::open(log_file)?;
+    }
+    #[cfg(feature = "tracing")]
+    {
+        let filter = match &args.log_level {
+            Some(level) => tracing_subscriber::EnvFilter::try_new(level)?,
+            None => tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+        };
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    // Both are publish with the status decided for you
+    match args.command.take() {
+        Some(Commands::Draft(mut options)) => {
+            if !matches!(options.post.metadata.status, None | Some(PublishStatus::Draft)) {
+                return Err(anyhow!("draft always publishes as a draft; use publish --status instead").into());
+            }
+            options.post.metadata.status = Some(PublishStatus::Draft);
+            args.command = Some(Commands::Publish(options));
+        }
+        Some(Commands::Promote(mut options)) => {
+            let target = options.file().to_string_lossy().into_owned();
+            let draft = history::latest_draft(&target)?
+                .ok_or_else(|| anyhow!("No draft of {} in your publishing history; save one with `draft` first", target))?;
+            options.post.metadata.status = match options.post.metadata.status {
+                Some(PublishStatus::Draft) => {
+                    return Err(anyhow!("promote never publishes a draft; use draft instead").into());
+                }
+                status => Some(status.unwrap_or(PublishStatus::Public)),
+            };
+            errln!(
+                "Warning: Medium can't publish an existing draft, so this publishes {} again; \
+                 the draft at {} stays until you delete it on Medium",
+                draft.file.display(),
+                draft.url
+            );
+            options.file = Some(draft.file);
+            args.command = Some(Commands::Publish(options));
+        }
+        command => args.command = command,
+    }
+
+    if let Some(Commands::Publish(options)) = &mut args.command {
+        if options.safe_mode {
+            let mut ignored = options.post.transform.enabled();
+            if !matches!(options.post.metadata.status, None | Some(PublishStatus::Draft)) {
+                ignored.push("--status");
+            }
+            options.post.metadata.status = Some(PublishStatus::Draft);
+            options.post.transform = TransformOptions {
+                no_normalize: true,
+                no_footer: true,
+                ..Default::default()
+            };
+            errln!(
+                "Safe mode: publishing as a draft, without uploading images, adding the canonical footer \
+                 or transforming the content"
+            );
+            if !ignored.is_empty() {
+                errln!("Safe mode: ignoring {}", ignored.join(", "));
+            }
+        }
+        if let Some(moved_from) = &options.republish_as_canonical {
+            options.post.transform.moved_from = Some(moved_from.to_string());
+        }
+        if options.metrics {
+            metrics::enable(args.json);
+        }
+        if options.watch {
+            // Saving often should never spam public posts
+            options.post.metadata.status = Some(PublishStatus::Draft);
+        }
+        if options.clipboard {
+            options.post.metadata.supplied_text = Some(read_clipboard()?);
+        }
+        if options.readme {
+            let readme = PathBuf::from("README.md");
+            let text = std::fs::read_to_string(&readme)
+                .map_err(|err| anyhow!("Could not read README.md in the current directory: {}", err))?;
+            let (title, body) = transform::readme_parts(&text);
+            if options.post.metadata.title.is_none() {
+                options.post.metadata.title = title;
+            }
+            options.post.metadata.supplied_text = Some(body);
+            // Kept as the file so relative images resolve next to it
+            options.file = Some(readme);
+        }
+        let url = options.file().to_str().and_then(|file| Url::parse(file).ok());
+        if let Some(url) = url.filter(|url| matches!(url.scheme(), "http" | "https")) {
+            // Sent with the saved User-Agent like every other request, though a
+            // dry run may have no config yet
+            let saved_user_agent = load_config().ok().and_then(|config| config.user_agent);
+            let client = args.client.build(saved_user_agent.as_deref())?;
+            options.post.metadata.supplied_text = Some(fetch_post(&client, &url).await?);
+            if !options.no_auto_canonical {
+                options.post.metadata.source_url = Some(url);
+            }
+        }
+        if options.file().is_dir() {
+            options.post.metadata.defaults = batch::load_defaults(options.file())?;
+        }
+    }
+
+    match &args.command {
+        Some(Commands::Init {
+            token,
+            token_file,
+            id,
+            profile,
+            ..
+        }) => {
+            let token = init_token(token.as_deref(), token_file.as_deref())?;
+            tee::redact(&token);
+            let file_path = init(&token, id.clone(), profile.as_deref(), &args.client).await?;
+            outln!("Saved token and author ID at {}", file_path.display());
+        }
+        Some(Commands::Publish(options)) if options.dry_run => {
+            dry_run(options, &args.client).await?;
+        }
+        Some(Commands::Publish(options)) if options.watch => {
+            if options.file().is_dir() {
+                return Err(anyhow!("--watch only applies to a single file").into());
+            }
+            let config = load_config()?;
+            let client = args.client.build(config.user_agent.as_deref())?;
+            watch::watch(options, &client, &config).await?;
+        }
+        Some(Commands::Publish(options)) if options.file().is_dir() => {
+            if options.split_on.is_some() {
+                return Err(anyhow!("--split-on only applies to a single file").into());
+            }
+            if !options.profiles.is_empty() {
+                return Err(anyhow!("--profiles only applies to a single file").into());
+            }
+            if options.output_template.is_some() {
+                return Err(anyhow!("--output-template only applies to a single file").into());
+            }
+            let config = load_config()?;
+            let client = args.client.build(config.user_agent.as_deref())?;
+            let summary = batch::publish_dir(options.file(), options, &client, &config, args.json).await?;
+            let timed_out = summary.timed_out();
+            if args.json {
+                outln!("{}", serde_json::to_string(&summary)?);
+            } else {
+                outln!(
+                    "Published {} files, {} failed, {} timed out, {} skipped",
+                    summary.succeeded.len(),
+                    summary.failed.len() - timed_out,
+                    timed_out,
+                    summary.skipped.len()
+                );
+            }
+            if !summary.remaining.is_empty() {
+                return Err(anyhow!(
+                    "Interrupted with {} files left; rerun with --resume to continue",
+                    summary.remaining.len()
+                )
+                .into());
+            }
+            let unpublished = summary.failed.len();
+            if unpublished > 0 {
+                return Err(anyhow!("{} files failed to publish; rerun with --resume to retry them", unpublished).into());
+            }
+        }
+        Some(Commands::Draft(_) | Commands::Promote(_)) => unreachable!("handled as publish"),
+        Some(Commands::Publish(options)) => {
+            if options.resume {
+                return Err(anyhow!("--resume only applies when publishing a directory").into());
+            }
+            if options.modified_within.is_some() {
+                return Err(anyhow!("--modified-within only applies when publishing a directory").into());
+            }
+            if options.sort.is_some() {
+                return Err(anyhow!("--sort only applies when publishing a directory").into());
+            }
+            if !options.add_tag_all.is_empty() {
+                return Err(anyhow!("--add-tag-all only applies when publishing a directory; use --tags instead").into());
+            }
+            if options.output_template.is_some() && (!options.profiles.is_empty() || options.split_on.is_some()) {
+                return Err(anyhow!("--output-template only applies when publishing a single post").into());
+            }
+            if !options.profiles.is_empty() {
+                let config = load_config()?;
+                let results = publish_profiles(options.file().to_owned(), options, &args.client, &config).await;
+                let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+                if failed > 0 {
+                    return Err(anyhow!("Failed to publish to {} of {} profiles", failed, results.len()).into());
+                }
+                outln!("{} Published to {} profiles", color::success("Done!"), results.len());
+                return Ok(());
+            }
+            if let Some(marker) = &options.split_on {
+                let config = load_config()?;
+                let client = args.client.build(config.user_agent.as_deref())?;
+                let urls = publish_split(options.file().to_owned(), marker, options, &client, &config).await?;
+                outln!("{} Published {} posts", color::success("Done!"), urls.len());
+                return Ok(());
+            }
+            let config = load_config()?;
+            let client = args.client.build(config.user_agent.as_deref())?;
+            let entry = publish(options.file().to_owned(), options, &client, &config).await?;
+            if args.json {
+                outln!(
+                    "{}",
+                    serde_json::json!({
+                        "url": entry.url,
+                        "title": entry.title,
+                        "status": entry.status,
+                        "id": entry.id,
+                    })
+                );
+            } else if let Some(template) = &options.output_template {
+                outln!("{}", render_output_template(template, &entry));
+            } else {
+                outln!("{} Your post has been published at {}", color::success("Done!"), entry.url);
+            }
+        }
+        Some(Commands::Preview { file, post }) => {
+            let file_path = preview(file.to_owned(), post)?;
+            outln!("Preview written to {}", file_path.display());
+        }
+        Some(Commands::Export { file, output, post }) => {
+            for target in export(file, output.as_deref(), post)? {
+                outln!("Wrote {}", target.display());
+            }
+        }
+        Some(Commands::Compile { file, output, post }) => {
+            compile(file, output.as_deref(), post)?;
+            if let Some(output) = output {
+                outln!("Wrote {}", output.display());
+            }
+        }
+        Some(Commands::Status) => {
+            status(&args.client).await?;
+        }
+        Some(Commands::Check) => {
+            let result = check(&args.client).await;
+            if args.json {
+                let report = match &result {
+                    Ok(user) => serde_json::json!({ "ok": true, "author_id": user.id }),
+                    Err(err) => serde_json::json!({ "ok": false, "error": err.to_string() }),
+                };
+                outln!("{}", report);
+                if result.is_err() {
+                    return Err(AlreadyReported.into());
+                }
+            } else {
+                let user = result.map_err(|err| anyhow!("Check failed: {}", err))?;
+                outln!("OK ({})", user.id);
+            }
+        }
+        Some(Commands::Config { action: ConfigAction::Edit }) => {
+            let file_path = edit_config()?;
+            outln!("Saved {}", file_path.display());
+        }
+        Some(Commands::Config { action: ConfigAction::Profiles }) => {
+            print_profiles(&read_config()?, args.json)?;
+        }
+        Some(Commands::Config {
+            action: ConfigAction::Remove { profile, yes },
+        }) => match remove_profile(profile, *yes)? {
+            Some(config) => {
+                if !args.json {
+                    outln!("Removed {}; remaining profiles:", profile);
+                }
+                print_profiles(&config, args.json)?;
+            }
+            None => outln!("Nothing removed"),
+        },
+        Some(Commands::Config {
+            action: ConfigAction::Reset { yes },
+        }) => match reset_config(*yes)? {
+            Some(file_path) => outln!("Deleted {}", file_path.display()),
+            None => outln!("Nothing deleted"),
+        },
+        Some(Commands::Series { action: SeriesAction::List { name } }) => {
+            for (series, entry) in history::series_parts(name)? {
+                outln!("{}. {}  {}", series.index, entry.title, entry.url);
+            }
+        }
+        Some(Commands::Series { action: SeriesAction::Link { name } }) => {
+            let parts = history::series_parts(name)?;
+            if parts.is_empty() {
+                return Err(anyhow!("No published parts of \"{}\" in the history", name).into());
+            }
+            outln!("**{}**\n", name);
+            for (series, entry) in parts {
+                let total = series.total.map(|total| format!(" of {}", total)).unwrap_or_default();
+                outln!("- Part {}{}: [{}]({})", series.index, total, entry.title, entry.url);
+            }
+        }
+        Some(Commands::Drafts { older_than, author_id }) => {
+            let author_id = match author_id {
+                Some(author_id) => author_id.to_owned(),
+                None => load_config()?.id,
+            };
+            let older_than = Duration::from_secs(older_than.saturating_mul(24 * 60 * 60));
+            for entry in history::stale_drafts(&author_id, older_than)? {
+                outln!("{}  {}  {}", entry.published_at, entry.title, entry.url);
+            }
+        }
+        Some(Commands::History { format }) => {
+            out!("{}", history::format_entries(&history::read_entries()?, format)?);
+        }
+        Some(Commands::Inspect {
+            file,
+            show_sources: true,
+            metadata,
+        }) => {
+            let parsed = parse_metadata(file, metadata, false)?;
+            out!("{}", resolved_metadata_report(file, &parsed, metadata)?);
+        }
+        Some(Commands::Inspect { file, metadata, .. }) => {
+            outln!("{}", serde_json::to_string_pretty(&inspect(file, metadata)?)?);
+        }
+        Some(Commands::Lint { file, encoding }) => {
+            let input = read_input(file, *encoding)?;
+            let overrides = MetadataOptions {
+                supplied_text: Some(input.clone()),
+                ..Default::default()
+            };
+            let parsed = parse_metadata(file, &overrides, false)?;
+            let front_matter_lines = input[..parsed.content_offset].matches('\n').count();
+            let warnings = validate::render_check(&parsed.content);
+            for warning in &warnings {
+                outln!("{}:{}: {}", file.display(), front_matter_lines + warning.line, warning.message);
+            }
+            if !warnings.is_empty() {
+                return Err(anyhow!("{} has markdown that won't render as intended", file.display()).into());
+            }
+        }
+        Some(Commands::Migrate {
+            dir,
+            site_url,
+            status,
+            report,
+        }) => {
+            let config = load_config()?;
+            let client = args.client.build(config.user_agent.as_deref())?;
+            let migrated = migrate::migrate(dir, site_url.as_ref(), status, &client, &config).await?;
+            if let Some(report) = report {
+                migrate::write_report(report, &migrated)?;
+                outln!("Wrote {}", report.display());
+            }
+            let failed = migrated.iter().filter(|entry| entry.error.is_some()).count();
+            if failed > 0 {
+                return Err(anyhow!("{} of {} posts failed to migrate", failed, migrated.len()).into());
+            }
+        }
+        Some(Commands::Posts { username }) => {
+            for post in posts(username.as_deref(), &args.client).await? {
+                match post.published {
+                    Some(published) => outln!("{}  {}  {}", published, post.title, post.url),
+                    None => outln!("{}  {}", post.title, post.url),
+                }
+            }
+        }
+        None => {
+            // Only reachable when global flags were given without a command
+            return Err(anyhow!("No command given; run `markmedium --help` to see the available commands").into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` parsed as if read from `post.md`
+    fn parse(text: &str) -> Result<ParsedPost> {
+        let overrides = MetadataOptions {
+            supplied_text: Some(text.to_string()),
+            ..Default::default()
+        };
+        parse_metadata(Path::new("post.md"), &overrides, false)
+    }
+
+    /// `text` parsed, then finished with the default transforms
+    fn finish(text: &str) -> String {
+        let parsed = parse(text).unwrap();
+        let mut metadata = PublishMetadata {
+            content: parsed.content,
+            ..parsed.metadata
+        };
+        finish_post(&mut metadata, &TransformOptions::default()).unwrap();
+        metadata.content
+    }
+
+    #[test]
+    fn byte_order_mark_is_skipped() {
+        let parsed = parse("\u{feff}---\ntitle: Hello\n---\nBody\n").unwrap();
+        assert_eq!(parsed.metadata.title, "Hello");
+        assert_eq!(parsed.content.trim(), "Body");
+        assert!(!parsed.content.contains('\u{feff}'));
+    }
+
+    #[test]
+    fn byte_order_mark_without_front_matter_is_dropped_from_the_body() {
+        let overrides = MetadataOptions {
+            supplied_text: Some("\u{feff}Body".to_string()),
+            title: Some("Hello".to_string()),
+            stdin_format: StdinFormat::Body,
+            ..Default::default()
+        };
+        let parsed = parse_metadata(Path::new("-"), &overrides, false).unwrap();
+        assert_eq!(parsed.content, "Body");
+    }
+
+    #[test]
+    fn cover_goes_above_the_content() {
+        assert_eq!(finish("---\ntitle: A\ncover: cover.png\n---\n\nBody\n"), "![](cover.png)\n\nBody");
+        assert_eq!(finish("---\ntitle: A\ncover: my cover.png\n---\nBody"), "![](<my cover.png>)\n\nBody");
+        assert_eq!(
+            finish("---\ntitle: A\ncover: a&b.png\ncontent_format: html\n---\n<p>Body</p>"),
+            "<img src=\"a&amp;b.png\">\n<p>Body</p>"
+        );
+        assert_eq!(finish("---\ntitle: A\n---\nBody"), "Body");
+    }
+
+    #[test]
+    fn content_warning_goes_below_the_cover() {
+        assert_eq!(
+            finish("---\ntitle: A\ncover: c.png\ncontent_warning: \"Spoilers\\nfor book two\"\n---\nBody"),
+            "![](c.png)\n\n> ⚠️ **Content warning:** Spoilers\n> for book two\n\nBody"
+        );
+        assert_eq!(
+            content_warning_note(" <gore> ", "html"),
+            "<blockquote><strong>⚠️ Content warning:</strong> &lt;gore&gt;</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn tags_may_be_a_list_or_a_comma_separated_string() {
+        let tags = |front_matter: &str| {
+            parse(&format!("---\ntitle: A\n{}\n---\nBody", front_matter)).map(|parsed| parsed.metadata.tags)
+        };
+        let expected = Some(vec!["rust".to_string(), "cli".to_string()]);
+        assert_eq!(tags("tags: \"rust, cli, , rust\"").unwrap(), expected);
+        assert_eq!(tags("tags: [rust, cli]").unwrap(), expected);
+        assert_eq!(tags("tags: \"\"").unwrap(), None);
+        assert_eq!(tags("tags:").unwrap(), None);
+
+        let err = tags("tags: 3").unwrap_err();
+        assert!(format!("{:#}", err).contains("`tags` must be a list or a comma-separated string"), "{:#}", err);
+        assert!(tags("tags: [rust, {a: 1}]").is_err());
+    }
+
+    #[tokio::test]
+    async fn retry_is_refused_before_uploading_images_again() {
+        let client = ClientOptions {
+            user_agent: None,
+            pool_max_idle_per_host: None,
+            max_concurrency_per_host: None,
+            headers: Vec::new(),
+            proxy: None,
+            verbose: false,
+            max_retry_wait: 0,
+            retry_jitter: 0,
+        }
+        .build(None)
+        .unwrap();
+        let config: ApiConfig = serde_json::from_value(serde_json::json!({"token": "token", "id": "me"})).unwrap();
+        let metadata = PublishMetadata {
+            title: format!("Retried {}", std::process::id()),
+            content: "![A missing image](missing.png)\n".to_string(),
+            ..Default::default()
+        };
+        let source = std::env::temp_dir().join("post.md");
+
+        // A first attempt whose response never arrived
+        let request = publish_request(&posts_url("me", None), &metadata).unwrap();
+        assert!(!history::begin_attempt(&request).unwrap());
+
+        // Skipping the image or failing on it would change the content sent,
+        // but neither retry gets as far as uploading it
+        for skip_failed_images in [true, false] {
+            let options = SubmitOptions {
+                skip_failed_images,
+                ..Default::default()
+            };
+            let result = submit(&client, &config, "me", None, source.clone(), metadata.clone(), options).await;
+            let Err(err) = result else {
+                panic!("the retry should be refused");
+            };
+            assert!(err.to_string().contains("rerun with --force"), "{}", err);
+        }
+        history::finish_attempt(&request).unwrap();
+    }
+
+    #[test]
+    fn output_template_fills_in_the_published_post() {
+        let entry = history::HistoryEntry::new(
+            PathBuf::from("post.md"),
+            "hello".to_string(),
+            "Hello".to_string(),
+            "me".to_string(),
+            None,
+            "https://medium.com/@me/hello-1a2b".to_string(),
+            PublishStatus::Draft,
+        );
+        let template = parse_output_template("{title} ({status}{id}): {url}").unwrap();
+        assert_eq!(
+            render_output_template(&template, &entry),
+            "Hello (draft): https://medium.com/@me/hello-1a2b"
+        );
+        assert!(parse_output_template("{link}").is_err());
+    }
 }
@@ -0,0 +1,136 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// A step of publishing a file that --metrics times
+#[derive(Clone, Copy)]
+pub enum Phase {
+    /// Reading the file and its front matter
+    Parse,
+    /// Content transformations, the footer and --pipe
+    Transform,
+    /// Uploading local images
+    Upload,
+    /// The request creating the post
+    Publish,
+}
+
+const PHASES: [(Phase, &str); 4] = [
+    (Phase::Parse, "parse"),
+    (Phase::Transform, "transform"),
+    (Phase::Upload, "upload"),
+    (Phase::Publish, "publish"),
+];
+
+/// Time spent in each phase by one publish attempt
+struct FileTimings {
+    label: String,
+    phases: [Duration; 4],
+}
+
+struct Metrics {
+    json: bool,
+    files: Vec<FileTimings>,
+}
+
+/// Only set by --metrics, so timing costs nothing otherwise
+static METRICS: OnceLock<Mutex<Metrics>> = OnceLock::new();
+
+pub fn enable(json: bool) {
+    let _ = METRICS.set(Mutex::new(Metrics { json, files: Vec::new() }));
+}
+
+/// Attribute the phases timed from now on to `label`, usually a file
+pub fn begin(label: String) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.lock().unwrap().files.push(FileTimings {
+            label,
+            phases: Default::default(),
+        });
+    }
+}
+
+/// Start timing a phase, when metrics are on
+pub fn start() -> Option<Instant> {
+    METRICS.get().map(|_| Instant::now())
+}
+
+/// Add the time since `started` to `phase` of the current file
+pub fn record(phase: Phase, started: Option<Instant>) {
+    let (Some(metrics), Some(started)) = (METRICS.get(), started) else {
+        return;
+    };
+    if let Some(file) = metrics.lock().unwrap().files.last_mut() {
+        file.phases[phase as usize] += started.elapsed();
+    }
+}
+
+/// Minimum, maximum, mean and sum of `durations`
+fn stats(durations: &[Duration]) -> [Duration; 4] {
+    let total: Duration = durations.iter().sum();
+    [
+        durations.iter().min().copied().unwrap_or_default(),
+        durations.iter().max().copied().unwrap_or_default(),
+        total / (durations.len().max(1) as u32),
+        total,
+    ]
+}
+
+/// Print each phase's timings across every file, as a table or JSON
+pub fn print_summary() {
+    let Some(metrics) = METRICS.get() else {
+        return;
+    };
+    let metrics = metrics.lock().unwrap();
+    let mut rows: Vec<(&str, [Duration; 4])> = PHASES
+        .iter()
+        .map(|(phase, name)| {
+            let durations: Vec<Duration> = metrics.files.iter().map(|file| file.phases[*phase as usize]).collect();
+            (*name, stats(&durations))
+        })
+        .collect();
+    let totals: Vec<Duration> = metrics.files.iter().map(|file| file.phases.iter().sum()).collect();
+    rows.push(("total", stats(&totals)));
+
+    if metrics.json {
+        let files: Vec<_> = metrics
+            .files
+            .iter()
+            .map(|file| {
+                let mut entry = serde_json::json!({ "file": file.label });
+                for (phase, name) in PHASES {
+                    entry[name] = file.phases[phase as usize].as_secs_f64().into();
+                }
+                entry
+            })
+            .collect();
+        let mut phases = serde_json::Map::new();
+        for (name, [min, max, avg, total]) in &rows {
+            phases.insert(
+                name.to_string(),
+                serde_json::json!({
+                    "min": min.as_secs_f64(),
+                    "max": max.as_secs_f64(),
+                    "avg": avg.as_secs_f64(),
+                    "total": total.as_secs_f64(),
+                }),
+            );
+        }
+        errln!("{}", serde_json::json!({ "files": files, "phases": phases }));
+        return;
+    }
+
+    errln!("Timings across {} files (seconds):", metrics.files.len());
+    errln!("{:9}  {:>8}  {:>8}  {:>8}  {:>8}", "phase", "min", "max", "avg", "total");
+    for (name, [min, max, avg, total]) in rows {
+        errln!(
+            "{:9}  {:>8.3}  {:>8.3}  {:>8.3}  {:>8.3}",
+            name,
+            min.as_secs_f64(),
+            max.as_secs_f64(),
+            avg.as_secs_f64(),
+            total.as_secs_f64()
+        );
+    }
+}
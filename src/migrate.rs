@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use yaml_front_matter::{Document, YamlFrontMatter};
+
+use crate::{batch, client::Client, csv_row, submit, validate, SubmitOptions, ApiConfig, PublishMetadata, PublishStatus};
+
+/// Front matter as Jekyll and Hugo write it
+#[derive(Deserialize)]
+struct SiteFrontMatter {
+    #[serde(default)]
+    title: String,
+    tags: Option<Terms>,
+    categories: Option<Terms>,
+    date: Option<String>,
+    permalink: Option<String>,
+}
+
+/// Jekyll accepts a space-separated string as well as a list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Terms {
+    List(Vec<String>),
+    Words(String),
+}
+
+impl Terms {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Terms::List(terms) => terms,
+            Terms::Words(words) => words.split_whitespace().map(str::to_string).collect(),
+        }
+    }
+}
+
+/// Where one source post ended up
+#[derive(Serialize)]
+pub struct Migrated {
+    pub source: PathBuf,
+    pub date: Option<String>,
+    pub url: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Split a post into its YAML `---` or, as Hugo also writes, TOML `+++`
+/// front matter and its body
+fn parse_front_matter(input: &str) -> Result<(SiteFrontMatter, String)> {
+    let Some(rest) = input.strip_prefix("+++") else {
+        let document: Document<SiteFrontMatter> = YamlFrontMatter::parse::<SiteFrontMatter>(input)
+            .map_err(|err| anyhow!("Invalid front matter: {}", err))?;
+        return Ok((document.metadata, document.content));
+    };
+    let rest = rest.trim_start_matches([' ', '\t']);
+    let rest = rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n')).unwrap_or(rest);
+    let mut offset = 0;
+    let mut body = None;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "+++" {
+            body = Some(&rest[offset + line.len()..]);
+            break;
+        }
+        offset += line.len();
+    }
+    let body = body.ok_or_else(|| anyhow!("Invalid front matter: the opening +++ is never closed"))?;
+    let table: toml::Table = toml::from_str(&rest[..offset]).map_err(|err| anyhow!("Invalid front matter: {}", err))?;
+    let front_matter = serde_json::from_value(toml_to_json(toml::Value::Table(table)))
+        .map_err(|err| anyhow!("Invalid front matter: {}", err))?;
+    Ok((front_matter, body.to_string()))
+}
+
+/// A TOML value as the JSON value YAML front matter would have given,
+/// with dates, which TOML has a type for, as strings
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(text) => text.into(),
+        toml::Value::Integer(number) => number.into(),
+        toml::Value::Float(number) => number.into(),
+        toml::Value::Boolean(flag) => flag.into(),
+        toml::Value::Datetime(date) => date.to_string().into(),
+        toml::Value::Array(items) => items.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table.into_iter().map(|(key, value)| (key, toml_to_json(value))).collect(),
+    }
+}
+
+fn convert(file: &Path, site_url: Option<&Url>, status: &PublishStatus) -> Result<(PublishMetadata, Option<String>)> {
+    let input = std::fs::read_to_string(file)?;
+    let input = input.strip_prefix('\u{feff}').unwrap_or(&input);
+    let (front_matter, content) = parse_front_matter(input)?;
+    if front_matter.title.trim().is_empty() {
+        return Err(anyhow!("a title is required"));
+    }
+
+    // Categories become tags too, after the post's own tags
+    let mut tags: Vec<String> = Vec::new();
+    let terms = front_matter.tags.into_iter().chain(front_matter.categories);
+    for tag in terms.flat_map(Terms::into_vec) {
+        if !validate::has_tag(&tags, &tag) {
+            tags.push(tag);
+        }
+    }
+
+    let canonical_url = match (site_url, &front_matter.permalink) {
+        (Some(site_url), Some(permalink)) => Some(site_url.join(permalink)?.to_string()),
+        _ => None,
+    };
+
+    let metadata = PublishMetadata {
+        title: front_matter.title,
+        content,
+        tags: (!tags.is_empty()).then_some(tags),
+        canonical_url,
+        status: Some(status.to_owned()),
+        ..PublishMetadata::default()
+    };
+    Ok((metadata, front_matter.date))
+}
+
+/// Publish every post in a Jekyll `_posts` or Hugo `content` folder
+pub async fn migrate(
+    dir: &Path,
+    site_url: Option<&Url>,
+    status: &PublishStatus,
+    client: &Client,
+    config: &ApiConfig,
+) -> Result<Vec<Migrated>> {
+    let mut report = Vec::new();
+
+    for file in batch::markdown_files(dir)? {
+        let path = dir.join(&file);
+        let mut date = None;
+        let result = async {
+            let (mut metadata, post_date) = convert(&path, site_url, status)?;
+            date = post_date;
+            let source = std::fs::canonicalize(&path)?;
+            crate::finish_post(&mut metadata, &Default::default())?;
+            let entry = submit(client, config, &config.id, None, source, metadata, SubmitOptions::default()).await?;
+            anyhow::Ok(entry.url)
+        }
+        .await;
+
+        match &result {
+            Ok(url) => outln!("Migrated {} to {}", file.display(), url),
+            Err(err) => errln!("Failed to migrate {}: {}", file.display(), err),
+        }
+        let (url, error) = match result {
+            Ok(url) => (Some(url), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        report.push(Migrated {
+            source: file,
+            date,
+            url,
+            error,
+        });
+    }
+
+    Ok(report)
+}
+
+/// Write the report as CSV when `path` ends in `.csv`, JSON otherwise
+pub fn write_report(path: &Path, report: &[Migrated]) -> Result<()> {
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let text = if is_csv {
+        let mut text = String::from("source,date,url,error\n");
+        for entry in report {
+            let fields = [
+                entry.source.to_string_lossy().into_owned(),
+                entry.date.clone().unwrap_or_default(),
+                entry.url.clone().unwrap_or_default(),
+                entry.error.clone().unwrap_or_default(),
+            ];
+            text += &csv_row(&fields);
+        }
+        text
+    } else {
+        serde_json::to_string_pretty(report)?
+    };
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_toml_front_matter() {
+        let input = "+++\ntitle = \"Hello\"\ndate = 2019-03-01T10:00:00Z\ntags = [\"rust\", \"cli\"]\n\
+                     categories = \"notes\"\npermalink = \"/hello/\"\n+++\nBody\n";
+        let (front_matter, content) = parse_front_matter(input).unwrap();
+        assert_eq!(front_matter.title, "Hello");
+        assert_eq!(front_matter.date.as_deref(), Some("2019-03-01T10:00:00Z"));
+        assert_eq!(front_matter.tags.unwrap().into_vec(), ["rust", "cli"]);
+        assert_eq!(front_matter.categories.unwrap().into_vec(), ["notes"]);
+        assert_eq!(front_matter.permalink.as_deref(), Some("/hello/"));
+        assert_eq!(content, "Body\n");
+    }
+
+    #[test]
+    fn reads_yaml_front_matter() {
+        let (front_matter, content) = parse_front_matter("---\ntitle: Hi\ntags: a b\n---\nBody").unwrap();
+        assert_eq!(front_matter.title, "Hi");
+        assert_eq!(front_matter.tags.unwrap().into_vec(), ["a", "b"]);
+        assert_eq!(content.trim(), "Body");
+    }
+
+    #[test]
+    fn refuses_unclosed_toml_front_matter() {
+        assert!(parse_front_matter("+++\ntitle = \"Hi\"\nBody\n").is_err());
+    }
+}
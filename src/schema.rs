@@ -0,0 +1,120 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use jsonschema::Validator;
+use serde_json::Value;
+use url::Url;
+
+/// Schemas compiled so far, by path, so a directory's files share one
+static COMPILED: Mutex<BTreeMap<PathBuf, Arc<Validator>>> = Mutex::new(BTreeMap::new());
+
+/// Compile the JSON schema at `path`, once however many posts it checks.
+/// Relative `$ref`s resolve against the schema's own location.
+pub fn load(path: &Path) -> Result<Arc<Validator>> {
+    if let Some(validator) = COMPILED.lock().unwrap().get(path) {
+        return Ok(validator.clone());
+    }
+
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("Could not read schema {}: {}", path.display(), err))?;
+    let schema: Value =
+        serde_json::from_str(&text).map_err(|err| anyhow!("Invalid schema {}: {}", path.display(), err))?;
+    let mut options = jsonschema::options();
+    if let Ok(base) = std::fs::canonicalize(path).map(Url::from_file_path) {
+        options = options.with_base_uri(base.map_err(|_| anyhow!("Invalid schema path {}", path.display()))?);
+    }
+    let validator = Arc::new(
+        options
+            .build(&schema)
+            .map_err(|err| anyhow!("Invalid schema {}: {}", path.display(), err))?,
+    );
+    COMPILED.lock().unwrap().insert(path.to_path_buf(), validator.clone());
+    Ok(validator)
+}
+
+/// Every way `value` breaks the schema, each naming where in `value` it is
+pub fn validate(validator: &Validator, value: &Value) -> Vec<String> {
+    validator
+        .iter_errors(value)
+        .map(|err| format!("{}: {}", location(err.instance_path().as_str()), err))
+        .collect()
+}
+
+/// A JSON pointer into front matter, like `/tags/0`, as `tags/0`
+fn location(pointer: &str) -> &str {
+    match pointer.trim_start_matches('/') {
+        "" => "front matter",
+        path => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn compile(schema: Value) -> Validator {
+        jsonschema::validator_for(&schema).unwrap()
+    }
+
+    #[test]
+    fn reports_each_problem_with_its_location() {
+        let validator = compile(json!({
+            "type": "object",
+            "required": ["title"],
+            "properties": {
+                "tags": { "type": "array", "maxItems": 2, "items": { "type": "string", "pattern": "^[a-z]+$" } },
+            },
+        }));
+        let problems = validate(&validator, &json!({ "tags": ["rust", "CLI", "x"] }));
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("front matter: "), "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.starts_with("tags: ")), "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.starts_with("tags/1: ")), "{:?}", problems);
+    }
+
+    #[test]
+    fn accepts_a_matching_value() {
+        let validator = compile(json!({ "properties": { "status": { "enum": ["draft", "public"] } } }));
+        assert!(validate(&validator, &json!({ "status": "draft" })).is_empty());
+    }
+
+    #[test]
+    fn supports_references_and_combinators() {
+        let validator = compile(json!({
+            "$defs": { "tag": { "type": "string", "maxLength": 25 } },
+            "properties": {
+                "tags": { "items": { "$ref": "#/$defs/tag" } },
+                "status": { "oneOf": [{ "const": "draft" }, { "const": "public" }] },
+            },
+            "if": { "properties": { "status": { "const": "public" } } },
+            "then": { "required": ["canonical_url"] },
+        }));
+        let problems = validate(&validator, &json!({ "tags": ["a".repeat(26)], "status": "public" }));
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.starts_with("tags/0: ")), "{:?}", problems);
+        assert!(validate(&validator, &json!({ "status": "draft" })).is_empty());
+    }
+
+    #[test]
+    fn loads_a_schema_once_and_refuses_invalid_ones() {
+        let dir = std::env::temp_dir().join(format!("markmedium-schema-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("schema.json");
+        std::fs::write(&path, r#"{ "properties": { "title": { "$ref": "title.json" } } }"#).unwrap();
+        std::fs::write(dir.join("title.json"), r#"{ "minLength": 3 }"#).unwrap();
+
+        let validator = load(&path).unwrap();
+        assert_eq!(validate(&validator, &json!({ "title": "Hi" })).len(), 1);
+        assert!(Arc::ptr_eq(&validator, &load(&path).unwrap()));
+
+        std::fs::write(dir.join("invalid.json"), r#"{ "type": 5 }"#).unwrap();
+        assert!(load(&dir.join("invalid.json")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+
+/// Where console output is mirrored, set by --log-file
+static LOG_FILE: OnceLock<Mutex<LogFile>> = OnceLock::new();
+/// Tokens to blank out of the log, whichever command printed them
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct LogFile {
+    file: File,
+    /// Whether the next write starts a line, so only line starts get a timestamp
+    at_line_start: bool,
+}
+
+/// Append everything printed from now on to `path`
+pub fn open(path: &Path) -> Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| anyhow!("Could not open log file {}: {}", path.display(), err))?;
+    let _ = LOG_FILE.set(Mutex::new(LogFile {
+        file,
+        at_line_start: true,
+    }));
+    Ok(())
+}
+
+/// Keep `secret` out of the log file
+pub fn redact(secret: &str) {
+    if !secret.is_empty() {
+        SECRETS.lock().unwrap().push(secret.to_string());
+    }
+}
+
+/// `text` without the escape codes that color it in a terminal
+fn strip_colors(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        plain.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        match rest.find(|c: char| c.is_ascii_alphabetic()) {
+            Some(end) => rest = &rest[end + 1..],
+            None => rest = "",
+        }
+    }
+    plain.push_str(rest);
+    plain
+}
+
+/// Mirror printed `text` into the log file, if one is open. A log that
+/// can't be written is never worth failing a publish over.
+pub fn write(text: &str) {
+    let Some(log) = LOG_FILE.get() else {
+        return;
+    };
+    let mut text = strip_colors(text);
+    for secret in SECRETS.lock().unwrap().iter() {
+        text = text.replace(secret.as_str(), "<token>");
+    }
+
+    let mut log = log.lock().unwrap();
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let mut stamped = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        if log.at_line_start {
+            stamped.push_str(&timestamp);
+            stamped.push(' ');
+        }
+        stamped.push_str(line);
+        log.at_line_start = line.ends_with('\n');
+    }
+    let _ = log.file.write_all(stamped.as_bytes());
+}
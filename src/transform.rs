@@ -0,0 +1,1237 @@
+use std::{
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{BrokenLink, Event, HeadingLevel, LinkType, Options, Parser, Tag, TagEnd};
+
+use crate::{emoji, escape_html, history, history::HistoryEntry, validate};
+
+/// Content transformations applied to the body before it is sent
+#[derive(clap::Args, Clone, Default)]
+pub struct TransformOptions {
+    /// Convert GFM tables, which Medium's markdown import mangles, into HTML tables
+    #[arg(long)]
+    pub fix_tables: bool,
+    /// Pipe the assembled content through a shell command and publish its
+    /// output instead. The command runs with your privileges, so only use
+    /// ones you trust.
+    #[arg(long, value_name = "COMMAND")]
+    pub pipe: Option<String>,
+    /// Insert a table of contents built from `##` and `###` headings after the first paragraph
+    #[arg(long)]
+    pub toc: bool,
+    /// Inline `{{include: path}}` directives in the body, resolving each
+    /// path relative to the file containing it
+    #[arg(long)]
+    pub expand_includes: bool,
+    /// Remove `<!-- comments -->` so editorial notes aren't published
+    #[arg(long)]
+    pub strip_comments: bool,
+    /// Rewrite reference-style links like `[text][ref]`, which Medium's
+    /// importer mishandles, as inline links and drop their definitions
+    #[arg(long)]
+    pub inline_links: bool,
+    /// Turn `[[Page Name]]` and `[[Page Name|alias]]` wiki-links into links,
+    /// to the Medium post of that title if you've published one, otherwise
+    /// under --wikilink-base. Others become plain text.
+    #[arg(long)]
+    pub resolve_wikilinks: bool,
+    /// Address that slugified wiki-link targets are appended to
+    #[arg(long, value_name = "URL", requires = "resolve_wikilinks")]
+    pub wikilink_base: Option<url::Url>,
+    /// Replace `PATTERN` with `VALUE` in the assembled content, e.g.
+    /// `--replace '{{year}}=2024'`. Repeat for more rules, applied in order.
+    #[arg(long, value_name = "PATTERN=VALUE", value_parser = parse_replacement)]
+    pub replace: Vec<(String, String)>,
+    /// Treat --replace patterns as regular expressions; values may use `$1`
+    #[arg(long, requires = "replace")]
+    pub regex_replace: bool,
+    /// Keep the body's line endings, trailing whitespace and runs of blank
+    /// lines as written instead of tidying them
+    #[arg(long)]
+    pub no_normalize: bool,
+    /// Text between the content and the "Originally published at" footer,
+    /// with `\n` for line breaks; empty for none [default: a `---` rule, or
+    /// `<hr>` for HTML]
+    #[arg(long, value_name = "TEXT", value_parser = parse_separator)]
+    pub footer_separator: Option<String>,
+    /// Turn GitHub-style shortcodes like `:rocket:` into emoji, which
+    /// Medium would otherwise show as written
+    #[arg(long)]
+    pub emojify: bool,
+    /// Leave out the "Originally published at" footer, for `publish --safe-mode`
+    #[arg(skip)]
+    pub no_footer: bool,
+    /// Where the post was first published on Medium, noted under the footer,
+    /// for `publish --republish-as-canonical`
+    #[arg(skip)]
+    pub moved_from: Option<String>,
+}
+
+fn parse_replacement(rule: &str) -> Result<(String, String), String> {
+    match rule.split_once('=') {
+        Some(("", _)) => Err("the pattern must not be empty".to_string()),
+        Some((pattern, value)) => Ok((pattern.to_string(), value.to_string())),
+        None => Err("expected `PATTERN=VALUE`".to_string()),
+    }
+}
+
+fn parse_separator(separator: &str) -> Result<String, String> {
+    Ok(separator.replace("\\n", "\n"))
+}
+
+impl TransformOptions {
+    /// Flags given that change the content, so --safe-mode can say what it turned off
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let flags = [
+            (self.fix_tables, "--fix-tables"),
+            (self.pipe.is_some(), "--pipe"),
+            (self.toc, "--toc"),
+            (self.expand_includes, "--expand-includes"),
+            (self.strip_comments, "--strip-comments"),
+            (self.inline_links, "--inline-links"),
+            (self.resolve_wikilinks, "--resolve-wikilinks"),
+            (!self.replace.is_empty(), "--replace"),
+            (self.footer_separator.is_some(), "--footer-separator"),
+            (self.emojify, "--emojify"),
+        ];
+        flags.into_iter().filter(|(on, _)| *on).map(|(_, flag)| flag).collect()
+    }
+
+    pub fn apply(&self, content: String, content_format: &str) -> String {
+        let mut content = content;
+        if !self.no_normalize {
+            content = normalize_whitespace(&content, content_format);
+        }
+        if self.strip_comments {
+            content = strip_comments(&content, content_format);
+        }
+        if self.emojify {
+            let (emojified, unknown) = emoji::emojify(&content, content_format);
+            content = emojified;
+            for shortcode in unknown {
+                errln!("Warning: unknown emoji shortcode :{}: left as written", shortcode);
+            }
+        }
+        if self.inline_links && content_format != "html" {
+            content = inline_reference_links(&content);
+        }
+        if self.resolve_wikilinks && content_format != "html" {
+            content = resolve_wikilinks(&content, self.wikilink_base.as_ref());
+        }
+        if self.toc {
+            content = insert_toc(&content, content_format);
+        }
+        if self.fix_tables && content_format != "html" {
+            content = transform_tables(&content);
+        }
+        content
+    }
+
+    /// Run the --replace rules over `content`, each on the previous one's output
+    pub fn replace_all(&self, content: &str) -> Result<String> {
+        let mut content = content.to_string();
+        for (pattern, value) in &self.replace {
+            content = if self.regex_replace {
+                let regex = regex::Regex::new(pattern)
+                    .map_err(|err| anyhow!("Invalid --replace pattern `{}`: {}", pattern, err))?;
+                regex.replace_all(&content, value.as_str()).into_owned()
+            } else {
+                content.replace(pattern.as_str(), value)
+            };
+        }
+        Ok(content)
+    }
+}
+
+struct Heading {
+    /// 0 for `##`, 1 for `###`
+    depth: usize,
+    text: String,
+    /// Existing `id` of an HTML heading
+    id: Option<String>,
+    /// Where an `id` attribute can be added to an HTML heading lacking one
+    id_position: Option<usize>,
+}
+
+/// Lowercase words joined by single dashes, e.g. "Hello, World!" as `hello-world`
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+/// Split a README into the text of its top `#` heading and the rest, also
+/// dropping the rows of badge images that usually surround that heading
+pub fn readme_parts(content: &str) -> (Option<String>, String) {
+    let badges = regex::Regex::new(r"^\s*(\[?!\[[^\]]*\]\([^)]*\)(\]\([^)]*\))?\s*)+$").unwrap();
+    let mut title = None;
+    let mut body = String::with_capacity(content.len());
+    let mut in_header = true;
+
+    for line in content.lines() {
+        if in_header {
+            if line.trim().is_empty() || badges.is_match(line) {
+                continue;
+            }
+            if title.is_none() {
+                if let Some(heading) = line.strip_prefix("# ") {
+                    title = Some(heading.trim().trim_end_matches('#').trim().to_string());
+                    continue;
+                }
+            }
+            in_header = false;
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+
+    (title, body)
+}
+
+/// Common words long enough to pass the length check but too vague to be tags
+const STOP_WORDS: &[&str] = &[
+    "about", "after", "again", "also", "because", "been", "before", "being", "between", "both", "could",
+    "does", "doing", "down", "each", "even", "every", "first", "from", "have", "here", "into", "just",
+    "know", "like", "made", "make", "many", "more", "most", "much", "must", "need", "only", "other",
+    "over", "really", "same", "should", "some", "such", "than", "that", "their", "them", "then", "there",
+    "these", "they", "thing", "things", "this", "those", "through", "time", "under", "until", "very",
+    "want", "were", "what", "when", "where", "which", "while", "will", "with", "without", "would",
+    "your", "yours",
+];
+
+/// Up to five tags for `content`: its `#hashtags` in order of appearance,
+/// then the words of four or more letters used at least twice, most
+/// frequent first and ties in order of appearance. Code and common words
+/// like "because" are ignored.
+pub fn suggest_tags(content: &str, content_format: &str) -> Vec<String> {
+    let text = if content_format == "html" {
+        strip_tags(content)
+    } else {
+        let mut text = String::new();
+        let mut in_code = false;
+        for event in Parser::new(content) {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(TagEnd::CodeBlock) => in_code = false,
+                Event::Text(part) if !in_code => text.push_str(&part),
+                Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push('\n'),
+                _ => {}
+            }
+        }
+        text
+    };
+
+    let mut tags: Vec<String> = Vec::new();
+    let hashtags = regex::Regex::new(r"(?:^|\s)#([A-Za-z][\w-]*)").unwrap();
+    for hashtag in hashtags.captures_iter(&text) {
+        if !validate::has_tag(&tags, &hashtag[1]) {
+            tags.push(hashtag[1].to_string());
+        }
+    }
+
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        if word.chars().count() < 4 || word.chars().all(char::is_numeric) || STOP_WORDS.contains(&word.as_str()) {
+            continue;
+        }
+        match counts.iter_mut().find(|(counted, _)| *counted == word) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((word, 1)),
+        }
+    }
+    // Stable, so equally frequent words keep their order of appearance
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (word, _) in counts.into_iter().filter(|(_, count)| *count >= 2) {
+        if !validate::has_tag(&tags, &word) {
+            tags.push(word);
+        }
+    }
+
+    tags.truncate(validate::MAX_TAGS);
+    tags
+}
+
+/// Pieces of `content` between occurrences of `marker`, skipping empty ones
+pub fn split_segments(content: &str, marker: &str) -> Vec<String> {
+    content
+        .split(marker)
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Text of the first heading of any level
+pub fn first_heading(content: &str, content_format: &str) -> Option<String> {
+    if content_format == "html" {
+        let lower = content.to_ascii_lowercase();
+        let start = (1..=6)
+            .filter_map(|level| lower.find(&format!("<h{}", level)).map(|start| (start, level)))
+            .min()?;
+        let open_end = start.0 + lower[start.0..].find('>')? + 1;
+        let end = open_end + lower[open_end..].find(&format!("</h{}>", start.1))?;
+        let text = strip_tags(&content[open_end..end]);
+        return Some(text.trim().to_string()).filter(|text| !text.is_empty());
+    }
+
+    let mut text = String::new();
+    let mut in_heading = false;
+    for event in Parser::new(content) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => in_heading = true,
+            Event::Text(part) | Event::Code(part) if in_heading => text.push_str(&part),
+            Event::End(TagEnd::Heading(_)) => {
+                return Some(text.trim().to_string()).filter(|text| !text.is_empty());
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn markdown_headings(content: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut current: Option<Heading> = None;
+
+    for event in Parser::new(content) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                let depth = match level {
+                    HeadingLevel::H2 => 0,
+                    HeadingLevel::H3 => 1,
+                    _ => continue,
+                };
+                current = Some(Heading {
+                    depth,
+                    text: String::new(),
+                    id: None,
+                    id_position: None,
+                });
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = current.as_mut() {
+                    heading.text.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => headings.extend(current.take()),
+            _ => {}
+        }
+    }
+
+    headings
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn html_headings(content: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let lower = content.to_ascii_lowercase();
+    let mut position = 0;
+
+    while let Some(start) = lower[position..].find("<h").map(|offset| position + offset) {
+        position = start + 2;
+        let depth = match lower.as_bytes().get(start + 2) {
+            Some(b'2') => 0,
+            Some(b'3') => 1,
+            _ => continue,
+        };
+        let close = format!("</h{}>", depth + 2);
+        let (Some(open_end), Some(end)) = (lower[start..].find('>'), lower[start..].find(&close)) else {
+            continue;
+        };
+        let open_tag = &content[start..start + open_end];
+        let inner = &content[start + open_end + 1..start + end];
+        let id = open_tag
+            .split_once("id=\"")
+            .and_then(|(_, rest)| rest.split_once('"'))
+            .map(|(id, _)| id.to_string());
+
+        // Drop any inline tags inside the heading
+        let text = strip_tags(inner);
+        let id_position = if id.is_none() { Some(start + 3) } else { None };
+        headings.push(Heading {
+            depth,
+            text,
+            id,
+            id_position,
+        });
+        position = start + end;
+    }
+
+    headings
+}
+
+/// A nested table of contents: bullets for markdown, linked lists for HTML
+pub fn generate_toc(content: &str, content_format: &str) -> String {
+    if content_format == "html" {
+        html_toc(&html_headings(content))
+    } else {
+        markdown_toc(&markdown_headings(content))
+    }
+}
+
+fn markdown_toc(headings: &[Heading]) -> String {
+    headings
+        .iter()
+        .map(|heading| format!("{}- {}", "  ".repeat(heading.depth), heading.text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn html_toc(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+
+    let mut toc = String::from("<ul>");
+    let mut depth = 0;
+    for heading in headings {
+        while depth < heading.depth {
+            toc.push_str("<ul>");
+            depth += 1;
+        }
+        while depth > heading.depth {
+            toc.push_str("</ul>");
+            depth -= 1;
+        }
+        let id = heading.id.clone().unwrap_or_else(|| slugify(&heading.text));
+        toc.push_str(&format!("<li><a href=\"#{}\">{}</a></li>", id, escape_html(&heading.text)));
+    }
+    toc.push_str(&"</ul>".repeat(depth + 1));
+    toc
+}
+
+/// Give HTML headings without an `id` the one their table of contents entry links to
+fn add_heading_ids(content: &str) -> String {
+    let mut output = content.to_string();
+    for heading in html_headings(content).iter().rev() {
+        if let Some(position) = heading.id_position {
+            output.insert_str(position, &format!(" id=\"{}\"", slugify(&heading.text)));
+        }
+    }
+    output
+}
+
+/// Place the table of contents after the first paragraph, or at the top if there is none
+fn insert_toc(content: &str, content_format: &str) -> String {
+    let toc = generate_toc(content, content_format);
+    if toc.is_empty() {
+        return content.to_string();
+    }
+    let content = if content_format == "html" {
+        add_heading_ids(content)
+    } else {
+        content.to_string()
+    };
+    let content = content.as_str();
+
+    let position = if content_format == "html" {
+        content.find("</p>").map(|end| end + "</p>".len())
+    } else {
+        Parser::new(content)
+            .into_offset_iter()
+            .find(|(event, _)| matches!(event, Event::End(TagEnd::Paragraph)))
+            .map(|(_, range)| range.end)
+    };
+
+    match position {
+        Some(position) => format!(
+            "{}\n\n{}\n\n{}",
+            content[..position].trim_end(),
+            toc,
+            content[position..].trim_start()
+        ),
+        None => format!("{}\n\n{}", toc, content),
+    }
+}
+
+/// Byte ranges of code spans and blocks, where `<!--` is literal text
+pub fn code_ranges(content: &str) -> Vec<Range<usize>> {
+    Parser::new(content)
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Code(_) | Event::Start(Tag::CodeBlock(_))))
+        .map(|(_, range)| range)
+        .collect()
+}
+
+/// Remove HTML comments, which end at the first `-->` and so don't nest.
+/// An unterminated comment runs to the end, as it would in a browser.
+fn strip_comments(content: &str, content_format: &str) -> String {
+    let code = if content_format == "html" {
+        Vec::new()
+    } else {
+        code_ranges(content)
+    };
+    let mut output = String::with_capacity(content.len());
+    let mut position = 0;
+
+    while let Some(start) = content[position..].find("<!--").map(|offset| position + offset) {
+        if let Some(range) = code.iter().find(|range| range.contains(&start)) {
+            output.push_str(&content[position..range.end]);
+            position = range.end;
+            continue;
+        }
+        output.push_str(&content[position..start]);
+        position = content[start + 4..]
+            .find("-->")
+            .map_or(content.len(), |end| start + 4 + end + 3);
+    }
+
+    output.push_str(&content[position..]);
+    output
+}
+
+/// Index of the `]` closing the `[` at `open`, honouring nesting and escapes
+fn closing_bracket(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (index, byte) in text.bytes().enumerate().skip(open) {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match byte {
+            b'\\' => escaped = true,
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn inline_link(source: &str, dest: &str, title: &str) -> Option<String> {
+    let open = source.find('[')?;
+    let close = closing_bracket(source, open)?;
+    let dest = if dest.contains([' ', '(', ')']) {
+        format!("<{}>", dest)
+    } else {
+        dest.to_string()
+    };
+    let title = if title.is_empty() {
+        String::new()
+    } else {
+        format!(" \"{}\"", title.replace('"', "\\\""))
+    };
+    Some(format!("{}({}{})", &source[..=close], dest, title))
+}
+
+/// One pass turning reference links and images that contain no others into
+/// inline ones, or `None` when there are none left
+fn inline_innermost_references(content: &str, warn: bool) -> Option<String> {
+    let mut broken = |link: BrokenLink| {
+        if warn && link.link_type != LinkType::Shortcut {
+            errln!("Warning: no definition for link reference [{}]", link.reference);
+        }
+        None
+    };
+    let mut found = Vec::new();
+    let parser = Parser::new_with_broken_link_callback(content, Options::empty(), Some(&mut broken));
+    for (event, range) in parser.into_offset_iter() {
+        if let Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            ..
+        })
+        | Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            ..
+        }) = event
+        {
+            let mut range = range;
+            if link_type == LinkType::Collapsed && content[range.end..].starts_with("[]") {
+                // The empty label isn't part of the reported range
+                range.end += 2;
+            }
+            if matches!(link_type, LinkType::Reference | LinkType::Collapsed | LinkType::Shortcut) {
+                found.push((range, dest_url.to_string(), title.to_string()));
+            }
+        }
+    }
+
+    let innermost: Vec<_> = found
+        .iter()
+        .filter(|(range, ..)| {
+            !found
+                .iter()
+                .any(|(other, ..)| other != range && range.start <= other.start && other.end <= range.end)
+        })
+        .collect();
+    if innermost.is_empty() {
+        return None;
+    }
+
+    let mut output = String::with_capacity(content.len());
+    let mut position = 0;
+    for (range, dest, title) in innermost {
+        let source = &content[range.clone()];
+        let Some(replacement) = inline_link(source, dest, title) else {
+            continue;
+        };
+        output.push_str(&content[position..range.start]);
+        output.push_str(&replacement);
+        position = range.end;
+    }
+    output.push_str(&content[position..]);
+    Some(output)
+}
+
+/// Inline every reference-style link, then remove the definitions they used
+pub fn inline_reference_links(content: &str) -> String {
+    let mut content = content.to_string();
+    let mut first = true;
+    while let Some(inlined) = inline_innermost_references(&content, first) {
+        if inlined == content {
+            break;
+        }
+        content = inlined;
+        first = false;
+    }
+
+    let parser = Parser::new(&content);
+    let mut spans: Vec<Range<usize>> = parser
+        .reference_definitions()
+        .iter()
+        .map(|(_, definition)| definition.span.clone())
+        .collect();
+    spans.sort_by_key(|span| span.start);
+
+    let mut output = String::with_capacity(content.len());
+    let mut position = 0;
+    for span in spans {
+        output.push_str(&content[position..span.start]);
+        position = span.end;
+        if content[position..].starts_with('\n') {
+            position += 1;
+        }
+    }
+    output.push_str(&content[position..]);
+    output.trim_end().to_string() + "\n"
+}
+
+/// Where `[[page]]` points: a published post of that title, or a page under `base`
+fn wikilink_target(page: &str, base: Option<&url::Url>, published: &[HistoryEntry]) -> Option<String> {
+    let (name, heading) = match page.split_once('#') {
+        Some((name, heading)) => (name.trim(), Some(heading.trim())),
+        None => (page.trim(), None),
+    };
+    let anchor = heading.map(|heading| format!("#{}", slugify(heading))).unwrap_or_default();
+
+    let slug = slugify(name);
+    let entry = published
+        .iter()
+        .rev()
+        .find(|entry| entry.title.eq_ignore_ascii_case(name) || (!slug.is_empty() && entry.slug == slug));
+    if let Some(entry) = entry {
+        return Some(format!("{}{}", entry.url, anchor));
+    }
+
+    let base = base?;
+    let mut base = base.to_string();
+    if !base.ends_with('/') {
+        base.push('/');
+    }
+    Some(format!("{}{}{}", base, slug, anchor))
+}
+
+/// Replace wiki-links outside of code, leaving `![[embeds]]` alone
+fn resolve_wikilinks(content: &str, base: Option<&url::Url>) -> String {
+    let published = history::read_entries().unwrap_or_default();
+    link_wikilinks(content, base, &published)
+}
+
+fn link_wikilinks(content: &str, base: Option<&url::Url>, published: &[HistoryEntry]) -> String {
+    let code = code_ranges(content);
+    let mut output = String::with_capacity(content.len());
+    let mut position = 0;
+
+    while let Some(start) = content[position..].find("[[").map(|offset| position + offset) {
+        let Some(length) = content[start..].find("]]") else {
+            break;
+        };
+        let end = start + length + 2;
+        let inner = &content[start + 2..start + length];
+        let embed = content[..start].ends_with('!');
+        let in_code = code.iter().any(|range| range.contains(&start));
+        if embed || in_code || inner.contains(['[', '\n']) || inner.trim().is_empty() {
+            output.push_str(&content[position..start + 2]);
+            position = start + 2;
+            continue;
+        }
+
+        output.push_str(&content[position..start]);
+        let (page, text) = match inner.split_once('|') {
+            Some((page, alias)) => (page, alias.trim()),
+            None => (inner, inner.split('#').next().unwrap_or(inner).trim()),
+        };
+        match wikilink_target(page, base, published) {
+            Some(url) => output.push_str(&format!("[{}]({})", text, url)),
+            None => {
+                errln!("Warning: no link found for [[{}]]; keeping it as text", inner);
+                output.push_str(text);
+            }
+        }
+        position = end;
+    }
+
+    output.push_str(&content[position..]);
+    output
+}
+
+const INCLUDE_START: &str = "{{include:";
+const INCLUDE_END: &str = "}}";
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Split raw input into its front matter block (if any) and the rest
+pub fn split_front_matter(input: &str) -> (&str, &str) {
+    let mut offset = 0;
+    let mut opened = false;
+    for line in input.split_inclusive('\n') {
+        offset += line.len();
+        if line.trim() == "---" {
+            if opened {
+                return input.split_at(offset);
+            }
+            opened = true;
+        } else if !opened && !line.trim().is_empty() {
+            break;
+        }
+    }
+    ("", input)
+}
+
+fn expand(text: &str, dir: &Path, stack: &mut Vec<PathBuf>) -> Result<String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(INCLUDE_START) {
+        let Some(length) = rest[start..].find(INCLUDE_END) else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+
+        let target = rest[start + INCLUDE_START.len()..start + length].trim();
+        let path = dir.join(target);
+        let canonical = std::fs::canonicalize(&path)
+            .map_err(|err| anyhow!("Could not include {}: {}", path.display(), err))?;
+        if stack.contains(&canonical) {
+            return Err(anyhow!("Include cycle: {} includes itself", canonical.display()));
+        }
+        if stack.len() >= MAX_INCLUDE_DEPTH {
+            return Err(anyhow!(
+                "Includes nested more than {} deep at {}",
+                MAX_INCLUDE_DEPTH,
+                path.display()
+            ));
+        }
+
+        let included = std::fs::read_to_string(&canonical)?;
+        let included_dir = canonical.parent().unwrap_or(dir).to_path_buf();
+        stack.push(canonical);
+        output.push_str(expand(included.trim_end_matches('\n'), &included_dir, stack)?.as_str());
+        stack.pop();
+
+        rest = &rest[start + length + INCLUDE_END.len()..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Recursively inline `{{include: path}}` directives below the front matter
+/// of `input`, which was read from `file`
+pub fn expand_includes(input: &str, file: &Path) -> Result<String> {
+    let (front_matter, body) = split_front_matter(input);
+    let dir = file.parent().unwrap_or_else(|| Path::new(""));
+    let mut stack: Vec<PathBuf> = std::fs::canonicalize(file).into_iter().collect();
+    Ok(format!("{}{}", front_matter, expand(body, dir, &mut stack)?))
+}
+
+/// Feed `content` to `command` on stdin and return what it writes to stdout
+pub fn pipe_through(command: &str, content: &str) -> Result<String> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut child = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread so a command that streams its output can't deadlock us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = content.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| anyhow!("Could not write to `{}`", command))?
+        .or_else(|err| match err.kind() {
+            // The command may legitimately stop reading early
+            std::io::ErrorKind::BrokenPipe => Ok(()),
+            _ => Err(err),
+        })?;
+
+    if !output.status.success() {
+        return Err(anyhow!("`{}` exited with {}", command, output.status));
+    }
+    String::from_utf8(output.stdout).map_err(|_| anyhow!("`{}` did not output valid UTF-8", command))
+}
+
+/// Use `\n` line endings, drop trailing whitespace and collapse three or
+/// more blank lines into two. Fenced code is left alone, and a markdown
+/// line ending in two spaces keeps them, since that is a line break.
+fn normalize_whitespace(content: &str, content_format: &str) -> String {
+    let markdown = content_format != "html";
+    let content = content.replace("\r\n", "\n").replace('\r', "\n");
+    let mut output = String::with_capacity(content.len());
+    let mut in_fence = false;
+    let mut blank_lines = 0;
+
+    for line in content.lines() {
+        let fence = markdown && is_fence(line);
+        if in_fence && !fence {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        }
+        if fence {
+            in_fence = !in_fence;
+        }
+
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            blank_lines += 1;
+            if blank_lines <= 2 {
+                output.push('\n');
+            }
+            continue;
+        }
+        blank_lines = 0;
+        output.push_str(trimmed);
+        if markdown && !fence && line.ends_with("  ") {
+            output.push_str("  ");
+        }
+        output.push('\n');
+    }
+
+    if !content.ends_with('\n') {
+        output.pop();
+    }
+    output
+}
+
+pub fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+/// Split a table row into cells, honouring `\|` escapes
+pub fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = if line.ends_with('|') && !line.ends_with("\\|") {
+        &line[..line.len() - 1]
+    } else {
+        line
+    };
+
+    let mut cells = vec![String::new()];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cells.last_mut().unwrap().push('|');
+                chars.next();
+            }
+            '|' => cells.push(String::new()),
+            _ => cells.last_mut().unwrap().push(c),
+        }
+    }
+
+    cells.into_iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+/// Number of columns of a delimiter row such as `| :--- | :---: |`, if it is one
+pub fn delimiter_columns(line: &str) -> Option<usize> {
+    delimiter_row(line).map(|aligns| aligns.len())
+}
+
+/// Alignments of a delimiter row such as `| :--- | :---: |`, if it is one
+fn delimiter_row(line: &str) -> Option<Vec<Align>> {
+    if !line.contains('-') {
+        return None;
+    }
+
+    split_row(line)
+        .iter()
+        .map(|cell| {
+            let left = cell.starts_with(':');
+            let right = cell.ends_with(':');
+            let dashes = cell.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (left, right) {
+                (true, true) => Align::Center,
+                (true, false) => Align::Left,
+                (false, true) => Align::Right,
+                (false, false) => Align::None,
+            })
+        })
+        .collect()
+}
+
+/// A cell's markdown as HTML, keeping emphasis, code and links but not the
+/// paragraph a cell would otherwise be wrapped in
+fn inline_html(cell: &str) -> String {
+    let events = Parser::new_ext(cell, Options::ENABLE_STRIKETHROUGH)
+        .filter(|event| !matches!(event, Event::Start(Tag::Paragraph) | Event::End(TagEnd::Paragraph)));
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+    html.trim_end().to_string()
+}
+
+fn push_row(html: &mut String, tag: &str, cells: &[String], aligns: &[Align]) {
+    html.push_str("<tr>");
+    for (index, align) in aligns.iter().enumerate() {
+        let cell = cells.get(index).map(String::as_str).unwrap_or("");
+        let style = match align {
+            Align::None => "",
+            Align::Left => " style=\"text-align: left\"",
+            Align::Center => " style=\"text-align: center\"",
+            Align::Right => " style=\"text-align: right\"",
+        };
+        html.push_str(&format!("<{tag}{style}>{}</{tag}>", inline_html(cell)));
+    }
+    html.push_str("</tr>");
+}
+
+/// Replace GFM tables outside of fenced code blocks with HTML `<table>`s
+pub fn transform_tables(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut output = Vec::with_capacity(lines.len());
+    let mut in_fence = false;
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+
+        let aligns = match lines.get(index + 1) {
+            Some(next) if !in_fence && line.contains('|') => delimiter_row(next),
+            _ => None,
+        };
+        let header = split_row(line);
+
+        match aligns {
+            Some(aligns) if aligns.len() == header.len() => {
+                let mut html = String::from("<table><thead>");
+                push_row(&mut html, "th", &header, &aligns);
+                html.push_str("</thead><tbody>");
+
+                index += 2;
+                while index < lines.len() && lines[index].contains('|') && !lines[index].trim().is_empty() {
+                    push_row(&mut html, "td", &split_row(lines[index]), &aligns);
+                    index += 1;
+                }
+
+                html.push_str("</tbody></table>");
+                output.push(html);
+            }
+            _ => {
+                output.push(line.to_string());
+                index += 1;
+            }
+        }
+    }
+
+    let mut transformed = output.join("\n");
+    if content.ends_with('\n') {
+        transformed.push('\n');
+    }
+    transformed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_keep_alignment() {
+        let html = transform_tables("| a | b | c | d |\n| --- | :-- | :-: | --: |\n| 1 | 2 | 3 | 4 |\n");
+        assert_eq!(
+            html,
+            "<table><thead><tr><th>a</th><th style=\"text-align: left\">b</th>\
+             <th style=\"text-align: center\">c</th><th style=\"text-align: right\">d</th></tr></thead>\
+             <tbody><tr><td>1</td><td style=\"text-align: left\">2</td>\
+             <td style=\"text-align: center\">3</td><td style=\"text-align: right\">4</td></tr></tbody></table>\n"
+        );
+    }
+
+    #[test]
+    fn tables_render_inline_markup() {
+        let html = transform_tables("| a | b |\n| - | - |\n| **x** | `y` |\n| [z](https://z.example) | ~~w~~ |");
+        assert!(html.contains("<td><strong>x</strong></td><td><code>y</code></td>"), "{}", html);
+        assert!(html.contains("<td><a href=\"https://z.example\">z</a></td><td><del>w</del></td>"), "{}", html);
+        assert!(!html.contains("<p>"), "{}", html);
+    }
+
+    #[test]
+    fn tables_escape_text() {
+        let html = transform_tables("| a |\n| - |\n| 1 < 2 & `<b>` |\n| x \\| y |");
+        assert!(html.contains("<td>1 &lt; 2 &amp; <code>&lt;b&gt;</code></td>"), "{}", html);
+        assert!(html.contains("<td>x | y</td>"), "{}", html);
+    }
+
+    #[test]
+    fn tables_in_fences_are_left_alone() {
+        let content = "```\n| a |\n| - |\n```\n";
+        assert_eq!(transform_tables(content), content);
+    }
+
+    #[test]
+    fn html_content_keeps_its_tables() {
+        let options = TransformOptions {
+            fix_tables: true,
+            ..Default::default()
+        };
+        let content = "| a |\n| - |\n| 1 |\n";
+        assert_eq!(options.apply(content.to_string(), "html"), content);
+    }
+
+    #[test]
+    fn toc_nests_third_level_headings() {
+        let content = "# Title\n\n## First\n\n### Inner `code`\n\n## Second\n\n#### Too deep\n";
+        assert_eq!(generate_toc(content, "markdown"), "- First\n  - Inner code\n- Second");
+    }
+
+    #[test]
+    fn toc_goes_after_the_first_paragraph() {
+        let content = "Intro text.\n\n## First\n\nBody.\n";
+        assert_eq!(insert_toc(content, "markdown"), "Intro text.\n\n- First\n\n## First\n\nBody.\n");
+        assert_eq!(insert_toc("## First\n", "markdown"), "- First\n\n## First\n");
+        assert_eq!(insert_toc("No headings.\n", "markdown"), "No headings.\n");
+    }
+
+    #[test]
+    fn html_toc_links_to_heading_ids() {
+        let content = "<p>Intro</p><h2>Getting started</h2><h3 id=\"setup\">Set <em>up</em></h3>";
+        assert_eq!(
+            insert_toc(content, "html"),
+            "<p>Intro</p>\n\n<ul><li><a href=\"#getting-started\">Getting started</a></li>\
+             <ul><li><a href=\"#setup\">Set up</a></li></ul></ul>\n\n\
+             <h2 id=\"getting-started\">Getting started</h2><h3 id=\"setup\">Set <em>up</em></h3>"
+        );
+    }
+
+    /// A fresh directory under the system temp dir for tests that read files
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("markmedium-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn front_matter_is_split_from_the_body() {
+        assert_eq!(split_front_matter("---\ntitle: A\n---\nBody\n"), ("---\ntitle: A\n---\n", "Body\n"));
+        assert_eq!(split_front_matter("\n---\na: 1\n---\n"), ("\n---\na: 1\n---\n", ""));
+        assert_eq!(split_front_matter("Body\n---\nMore\n---\n"), ("", "Body\n---\nMore\n---\n"));
+        assert_eq!(split_front_matter("---\nunclosed\n"), ("", "---\nunclosed\n"));
+    }
+
+    #[test]
+    fn includes_are_expanded_recursively() {
+        let dir = scratch_dir("includes");
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        std::fs::write(dir.join("parts/outer.md"), "Outer {{include: inner.md}}\n").unwrap();
+        std::fs::write(dir.join("parts/inner.md"), "inner\n").unwrap();
+        let post = dir.join("post.md");
+        let input = "---\nnote: {{include: nope.md}}\n---\nStart\n{{include: parts/outer.md}}\nEnd\n";
+
+        let expanded = expand_includes(input, &post).unwrap();
+        assert_eq!(expanded, "---\nnote: {{include: nope.md}}\n---\nStart\nOuter inner\nEnd\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_and_missing_files_are_errors() {
+        let dir = scratch_dir("include-cycle");
+        std::fs::write(dir.join("a.md"), "{{include: b.md}}").unwrap();
+        std::fs::write(dir.join("b.md"), "{{include: a.md}}").unwrap();
+        let post = dir.join("a.md");
+
+        let err = expand_includes("{{include: b.md}}", &post).unwrap_err();
+        assert!(err.to_string().starts_with("Include cycle"), "{}", err);
+        let err = expand_includes("{{include: missing.md}}", &post).unwrap_err();
+        assert!(err.to_string().starts_with("Could not include"), "{}", err);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replace_rules_apply_in_order() {
+        let options = TransformOptions {
+            replace: vec![
+                parse_replacement("{{year}}=2024").unwrap(),
+                parse_replacement("2024=$1 2024 (a=b)").unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(options.replace_all("© {{year}}, {{year}}").unwrap(), "© $1 2024 (a=b), $1 2024 (a=b)");
+        assert!(parse_replacement("=x").is_err());
+        assert!(parse_replacement("x").is_err());
+    }
+
+    #[test]
+    fn regex_replace_uses_capture_groups() {
+        let mut options = TransformOptions {
+            replace: vec![parse_replacement(r"v(\d+)\.(\d+)=version $1 (minor $2)").unwrap()],
+            regex_replace: true,
+            ..Default::default()
+        };
+        assert_eq!(options.replace_all("See v1.2.").unwrap(), "See version 1 (minor 2).");
+
+        options.replace = vec![parse_replacement("(unclosed=x").unwrap()];
+        let err = options.replace_all("text").unwrap_err();
+        assert!(err.to_string().starts_with("Invalid --replace pattern `(unclosed`"), "{}", err);
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        assert_eq!(strip_comments("a<!-- one -->b<!-- <!-- two -->c\n", "markdown"), "abc\n");
+        assert_eq!(strip_comments("kept\n<!-- never closed\n\nmore", "markdown"), "kept\n");
+        assert_eq!(strip_comments("<p>a<!--x--></p>", "html"), "<p>a</p>");
+    }
+
+    #[test]
+    fn comments_in_code_are_kept() {
+        let content = "`<!-- inline -->` x<!-- y -->\n\n```html\n<!-- fenced -->\n```\n";
+        assert_eq!(strip_comments(content, "markdown"), "`<!-- inline -->` x\n\n```html\n<!-- fenced -->\n```\n");
+        // HTML has no markdown code spans to protect
+        assert_eq!(strip_comments("`<!-- x -->`", "html"), "``");
+    }
+
+    fn published(title: &str, slug: &str, url: &str) -> HistoryEntry {
+        HistoryEntry::new(
+            PathBuf::from(format!("{}.md", slug)),
+            slug.to_string(),
+            title.to_string(),
+            "me".to_string(),
+            None,
+            url.to_string(),
+            crate::PublishStatus::Public,
+        )
+    }
+
+    #[test]
+    fn wikilinks_prefer_published_posts() {
+        let base = url::Url::parse("https://notes.example/wiki").unwrap();
+        let posts = [
+            published("Hello World", "hello-world", "https://medium.com/@me/old"),
+            published("Hello World", "hello-world", "https://medium.com/@me/new"),
+        ];
+        assert_eq!(
+            wikilink_target("hello world", Some(&base), &posts).as_deref(),
+            Some("https://medium.com/@me/new")
+        );
+        assert_eq!(
+            wikilink_target("Hello, World! # Part Two ", None, &posts).as_deref(),
+            Some("https://medium.com/@me/new#part-two")
+        );
+        assert_eq!(
+            wikilink_target("Other Page#Notes", Some(&base), &posts).as_deref(),
+            Some("https://notes.example/wiki/other-page#notes")
+        );
+        assert_eq!(wikilink_target("Other Page", None, &posts), None);
+    }
+
+    #[test]
+    fn wikilinks_become_markdown_links() {
+        let base = url::Url::parse("https://notes.example/").unwrap();
+        let posts = [published("Intro", "intro", "https://medium.com/@me/intro")];
+        let content = "See [[Intro]], [[Intro#Setup|setup]] and [[Missing]]. ![[embed.png]] `[[code]]`";
+        assert_eq!(
+            link_wikilinks(content, Some(&base), &posts),
+            "See [Intro](https://medium.com/@me/intro), [setup](https://medium.com/@me/intro#setup) \
+             and [Missing](https://notes.example/missing). ![[embed.png]] `[[code]]`"
+        );
+        assert_eq!(link_wikilinks("[[Missing|it]] stays text", None, &posts), "it stays text");
+    }
+
+    #[test]
+    fn reference_links_are_inlined() {
+        let content = "Read [the docs][docs], [Docs][] or [docs].\n\n[docs]: https://docs.example 'The \"docs\"'\n";
+        let inlined = r##"https://docs.example "The \"docs\"""##;
+        assert_eq!(
+            inline_reference_links(content),
+            format!("Read [the docs]({0}), [Docs]({0}) or [docs]({0}).\n", inlined)
+        );
+    }
+
+    #[test]
+    fn nested_reference_images_are_inlined() {
+        let content = "[![logo][img]][home]\n\n[img]: <logo (1).png>\n[home]: https://home.example\n";
+        assert_eq!(inline_reference_links(content), "[![logo](<logo (1).png>)](https://home.example)\n");
+    }
+
+    #[test]
+    fn unknown_references_are_left_as_text() {
+        let content = "A [b] and [c][d] with `[x][y]`\n";
+        assert_eq!(inline_reference_links(content), content);
+    }
+
+    #[test]
+    fn whitespace_is_normalized() {
+        let content = "Title \t\r\nline  \r\nbreak   \n\n\n\n\nend\rlast";
+        assert_eq!(normalize_whitespace(content, "markdown"), "Title\nline  \nbreak  \n\n\nend\nlast");
+        assert_eq!(normalize_whitespace("<p>a</p>  \n\n\n\n<p>b</p>\n", "html"), "<p>a</p>\n\n\n<p>b</p>\n");
+    }
+
+    #[test]
+    fn fenced_code_keeps_its_whitespace() {
+        let content = "```\ncode  \t\n\n\n\n\nmore \n```  \n";
+        assert_eq!(normalize_whitespace(content, "markdown"), "```\ncode  \t\n\n\n\n\nmore \n```\n");
+    }
+}
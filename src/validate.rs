@@ -0,0 +1,320 @@
+use std::{fmt, path::Path};
+
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use regex::Regex;
+
+use crate::transform;
+
+/// A problem that keeps a post from being published
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Where the checks before publishing report problems. Normally the first
+/// one fails the publish; with --collect-errors they are all kept, so one
+/// run lists everything to fix.
+pub struct Diagnostics {
+    collect: bool,
+    found: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new(collect: bool) -> Self {
+        Diagnostics {
+            collect,
+            found: Vec::new(),
+        }
+    }
+
+    /// Report a problem, which is the error straight away unless collecting
+    pub fn error(&mut self, message: impl Into<String>) -> Result<()> {
+        let diagnostic = Diagnostic {
+            message: message.into(),
+        };
+        if !self.collect {
+            return Err(anyhow!(diagnostic.message));
+        }
+        self.found.push(diagnostic);
+        Ok(())
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.found
+    }
+
+    /// Fail with every collected problem of `mdfile`, if there are any
+    pub fn finish(self, mdfile: &Path) -> Result<()> {
+        let found = self.into_vec();
+        match found.len() {
+            0 => Ok(()),
+            1 => Err(anyhow!(found[0].message.clone())),
+            count => {
+                let list: Vec<String> = found
+                    .iter()
+                    .map(|diagnostic| format!("\n  - {}", diagnostic.message.replace('\n', "\n  ")))
+                    .collect();
+                Err(anyhow!("{}: {} problems found:{}", mdfile.display(), count, list.concat()))
+            }
+        }
+    }
+}
+
+/// A leftover marker such as `TODO`, with the 1-based line it was found on
+#[derive(Debug)]
+pub struct MarkerHit {
+    pub line: usize,
+    pub marker: String,
+}
+
+fn is_word_boundary(c: Option<char>) -> bool {
+    c.is_none_or(|c| !c.is_alphanumeric() && c != '_')
+}
+
+/// Find whole-word occurrences of `markers` in `content`
+pub fn find_markers(content: &str, markers: &[String]) -> Vec<MarkerHit> {
+    let mut hits = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        for marker in markers.iter().filter(|marker| !marker.is_empty()) {
+            let found = line.match_indices(marker.as_str()).any(|(start, _)| {
+                let before = line[..start].chars().next_back();
+                let after = line[start + marker.len()..].chars().next();
+                is_word_boundary(before) && is_word_boundary(after)
+            });
+            if found {
+                hits.push(MarkerHit {
+                    line: index + 1,
+                    marker: marker.to_owned(),
+                });
+            }
+        }
+    }
+
+    hits
+}
+
+/// 1-based lines where TeX math starts, outside of code: `$$...$$`,
+/// `\[...\]`, `\(...\)` and `$...$`. Like Pandoc, a single `$` only opens math
+/// when not followed by a space and only closes it when not followed by a
+/// digit, so prices like "$5 and $10" aren't mistaken for it.
+pub fn find_math(content: &str, content_format: &str) -> Vec<usize> {
+    let math = Regex::new(r"\$\$[\s\S]+?\$\$|\\\[[\s\S]+?\\\]|\\\(.+?\\\)|\$[^\s$](?:[^$\n]*[^\s$\\])?\$").unwrap();
+    let code = if content_format == "html" {
+        Vec::new()
+    } else {
+        transform::code_ranges(content)
+    };
+
+    let mut lines = Vec::new();
+    for found in math.find_iter(content) {
+        let after = content[found.end()..].chars().next();
+        if found.as_str().starts_with('$')
+            && !found.as_str().starts_with("$$")
+            && after.is_some_and(|c| c.is_ascii_digit())
+        {
+            continue;
+        }
+        if code.iter().any(|range| range.contains(&found.start())) {
+            continue;
+        }
+        let line = content[..found.start()].matches('\n').count() + 1;
+        if !lines.contains(&line) {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Longest title Medium keeps intact; longer ones are cut off unpredictably
+pub const MAX_TITLE_LENGTH: usize = 100;
+
+/// `title` cut to `MAX_TITLE_LENGTH` characters, at the last space when there
+/// is one in the second half so words aren't split
+pub fn truncate_title(title: &str) -> String {
+    let cut: String = title.chars().take(MAX_TITLE_LENGTH).collect();
+    if title.chars().nth(MAX_TITLE_LENGTH).is_none_or(char::is_whitespace) {
+        return cut.trim_end().to_string();
+    }
+    match cut.rfind(char::is_whitespace) {
+        Some(space) if cut[..space].chars().count() >= MAX_TITLE_LENGTH / 2 => cut[..space].trim_end().to_string(),
+        _ => cut,
+    }
+}
+
+/// Something in the markdown that won't render the way it was likely meant
+#[derive(Debug)]
+pub struct RenderWarning {
+    /// 1-based line of the content
+    pub line: usize,
+    pub message: String,
+}
+
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+/// The fence characters opening or closing a code block on `line`
+fn fence(line: &str) -> Option<(char, usize)> {
+    let line = line.trim_start();
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    Some((marker, line.chars().take_while(|c| *c == marker).count())).filter(|(_, length)| *length >= 3)
+}
+
+/// Find markdown that parses, as markdown always does, but not into what
+/// was intended: code fences never closed, tables whose header and
+/// delimiter rows disagree, links to undefined references and link syntax
+/// left as literal text
+pub fn render_check(content: &str) -> Vec<RenderWarning> {
+    let mut warnings = Vec::new();
+
+    let lines: Vec<&str> = content.lines().collect();
+    let mut open_fence: Option<(usize, char, usize)> = None;
+    for (index, line) in lines.iter().enumerate() {
+        match (open_fence, fence(line)) {
+            (None, Some((marker, length))) => open_fence = Some((index, marker, length)),
+            (Some((_, open_marker, open_length)), Some((marker, length)))
+                if marker == open_marker && length >= open_length && line.trim().chars().all(|c| c == marker) =>
+            {
+                open_fence = None
+            }
+            (None, None) => {
+                let columns = lines.get(index + 1).and_then(|next| transform::delimiter_columns(next));
+                let header = transform::split_row(line).len();
+                if let Some(columns) = columns.filter(|columns| line.contains('|') && *columns != header) {
+                    warnings.push(RenderWarning {
+                        line: index + 1,
+                        message: format!(
+                            "table header has {} columns but its delimiter row has {}, so it won't render as a table",
+                            header, columns
+                        ),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((index, ..)) = open_fence {
+        warnings.push(RenderWarning {
+            line: index + 1,
+            message: "code fence is never closed, so everything after it is code".to_string(),
+        });
+    }
+
+    let mut undefined = Vec::new();
+    let mut broken = |link: pulldown_cmark::BrokenLink| {
+        if link.link_type != pulldown_cmark::LinkType::Shortcut {
+            undefined.push((link.span.start, link.reference.to_string()));
+        }
+        None
+    };
+    let parser = Parser::new_with_broken_link_callback(content, Options::all(), Some(&mut broken));
+    let mut in_code = false;
+    // Where the last text ending in `]` stopped, since `](` that didn't make
+    // a link comes out as separate text either side of it
+    let mut bracket_end = None;
+    for (event, range) in parser.into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(TagEnd::CodeBlock) => in_code = false,
+            Event::Text(text) if !in_code => {
+                if text.contains("](") || (text.starts_with('(') && bracket_end == Some(range.start)) {
+                    warnings.push(RenderWarning {
+                        line: line_of(content, range.start),
+                        message: "`](` is shown as text; check the link's brackets and parentheses".to_string(),
+                    });
+                }
+                bracket_end = Some(range.end).filter(|_| text.ends_with(']'));
+            }
+            _ => {}
+        }
+    }
+    for (offset, reference) in undefined {
+        warnings.push(RenderWarning {
+            line: line_of(content, offset),
+            message: format!("link reference [{}] is never defined", reference),
+        });
+    }
+
+    warnings.sort_by_key(|warning| warning.line);
+    warnings
+}
+
+/// Medium keeps at most this many tags on a post
+pub const MAX_TAGS: usize = 5;
+
+/// Whether `tag` is among `tags`, ignoring case
+pub fn has_tag(tags: &[String], tag: &str) -> bool {
+    tags.iter().any(|existing| existing.eq_ignore_ascii_case(tag))
+}
+
+/// Medium rejects tags longer than this many characters
+pub const MAX_TAG_LENGTH: usize = 25;
+
+/// Why Medium would reject `tag`, if it would
+pub fn tag_problem(tag: &str) -> Option<String> {
+    if tag.trim().is_empty() {
+        return Some("is empty".to_string());
+    }
+    if tag.contains(',') {
+        return Some("contains a comma; list each tag separately or use --split-tag-commas".to_string());
+    }
+    if tag.chars().count() > MAX_TAG_LENGTH {
+        return Some(format!("is longer than {} characters", MAX_TAG_LENGTH));
+    }
+    tag.chars()
+        .find(|c| c.is_control() || matches!(c, '#' | '<' | '>' | '"' | '/' | '\\'))
+        .map(|c| format!("contains {:?}, which Medium doesn't allow in tags", c))
+}
+
+/// Split tags written as `"rust, cli"` into separate ones, dropping empty
+/// pieces and repeats
+pub fn split_tag_commas(tags: &[String]) -> Vec<String> {
+    let mut split: Vec<String> = Vec::new();
+    for tag in tags.iter().flat_map(|tag| tag.split(',')).map(str::trim) {
+        if !tag.is_empty() && !has_tag(&split, tag) {
+            split.push(tag.to_string());
+        }
+    }
+    split
+}
+
+/// Allowed tags read from a `--tags-file`, one per line
+#[derive(Clone, Debug)]
+pub struct TagVocabulary {
+    tags: Vec<String>,
+}
+
+impl TagVocabulary {
+    pub fn parse(text: &str) -> TagVocabulary {
+        let tags = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_lowercase)
+            .collect();
+        TagVocabulary { tags }
+    }
+
+    /// Tags not in the vocabulary, compared case-insensitively
+    pub fn unknown<'a>(&self, tags: &'a [String]) -> Vec<&'a str> {
+        tags.iter()
+            .filter(|tag| !self.tags.contains(&tag.to_lowercase()))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Clap value parser so the file is read once, however many posts use it
+pub fn read_tag_vocabulary(path: &str) -> Result<TagVocabulary, String> {
+    std::fs::read_to_string(path)
+        .map(|text| TagVocabulary::parse(&text))
+        .map_err(|err| format!("could not read {}: {}", path, err))
+}
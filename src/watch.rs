@@ -0,0 +1,59 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::{client::Client, publish, ApiConfig, PublishArgs};
+
+/// How long saves must stop before the file is republished
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Republish the file as a draft every time it is saved, until Ctrl-C
+pub async fn watch(options: &PublishArgs, client: &Client, config: &ApiConfig) -> Result<()> {
+    let file = std::fs::canonicalize(options.file())?;
+    let dir = file
+        .parent()
+        .ok_or_else(|| anyhow!("Cannot watch {}", file.display()))?
+        .to_path_buf();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let watched = file.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // Editors often save by replacing the file, so watch its directory
+        // and pick out events that touch it
+        if let Ok(event) = event {
+            if !event.kind.is_access() && event.paths.iter().any(|path| path == &watched) {
+                let _ = sender.send(());
+            }
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    outln!("Watching {} (Ctrl-C to stop)", options.file().display());
+    republish(&file, options, client, config).await;
+
+    loop {
+        tokio::select! {
+            changed = receiver.recv() => {
+                if changed.is_none() {
+                    break;
+                }
+                while tokio::time::timeout(DEBOUNCE, receiver.recv()).await.is_ok_and(|event| event.is_some()) {}
+                republish(&file, options, client, config).await;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    outln!("Stopped watching {}", options.file().display());
+    Ok(())
+}
+
+/// Publish once, reporting errors without ending the watch
+async fn republish(file: &Path, options: &PublishArgs, client: &Client, config: &ApiConfig) {
+    match publish(file.to_path_buf(), options, client, config).await {
+        Ok(entry) => outln!("Draft updated at {}", entry.url),
+        Err(err) => errln!("Failed to publish {}: {}", options.file().display(), err),
+    }
+}
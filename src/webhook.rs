@@ -0,0 +1,61 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::client::Client;
+
+/// How long a webhook may take before it is given up on
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a webhook receives after each publish attempt
+#[derive(Serialize)]
+struct Payload<'a> {
+    file: &'a Path,
+    /// Missing when the file couldn't be parsed
+    title: Option<&'a str>,
+    /// `success` or `failure`
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// POST the outcome of publishing `file` to `webhook`. A webhook that fails
+/// or is slow only gets a warning, since the post itself is already out.
+pub async fn notify(
+    client: &Client,
+    webhook: &str,
+    file: &Path,
+    title: Option<&str>,
+    result: Result<&str, &anyhow::Error>,
+) {
+    let payload = match result {
+        Ok(url) => Payload {
+            file,
+            title,
+            status: "success",
+            url: Some(url),
+            error: None,
+        },
+        Err(err) => Payload {
+            file,
+            title,
+            status: "failure",
+            url: None,
+            error: Some(err.to_string()),
+        },
+    };
+
+    let response = client
+        .send(client.post(webhook).timeout(TIMEOUT).json(&payload))
+        .await;
+    match response {
+        Ok(response) if !response.status().is_success() => {
+            errln!("Warning: webhook {} answered {}", webhook, response.status());
+        }
+        Err(err) => errln!("Warning: could not notify webhook {}: {}", webhook, err),
+        Ok(_) => {}
+    }
+}